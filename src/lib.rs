@@ -0,0 +1,2517 @@
+/*
+ * Grido is a console game
+ * Copyright (C) 2015, 2016 Petr Machata <pmachata@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// The game itself: the grid that things are drawn on, tiles, blocks
+// and what happens when they are dropped and explode.  None of it
+// talks to the terminal, that is up to the grido binary, which
+// renders grids with ncurses and drives a game from key presses.  A
+// step of the simulation is simulate_drop, or Block::drop followed by
+// Block::explode for the pieces, and paint_board shows the result.
+//
+// The binary needs the frontend-ncurses feature, which is on by
+// default.  Without it, only this library is built, and nothing that
+// needs a terminal gets pulled in:
+//
+//     cargo build --lib --no-default-features
+
+extern crate rand;
+
+use rand::{Rng, SeedableRng, StdRng};
+use std::cmp;
+use std::fmt;
+use std::iter;
+use std::str;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Pen {
+    None,
+    Thin,
+    Thik,
+
+    // Dashed lines only exist straight, and rounded ones only as
+    // corners.  Elsewhere, both are drawn as thin lines.
+    Dash,
+    Round,
+}
+
+impl Pen {
+    fn combine(p1: Pen, p2: Pen) -> Pen {
+        match (p1, p2) {
+            (Pen::None, p) => p,
+            (p, Pen::None) => p,
+            (Pen::Thik, _) |
+            (_, Pen::Thik) => Pen::Thik,
+            (Pen::Thin, _) |
+            (_, Pen::Thin) => Pen::Thin,
+            (Pen::Round, _) |
+            (_, Pen::Round) => Pen::Round,
+            (Pen::Dash, Pen::Dash) => Pen::Dash,
+        }
+    }
+
+    fn thinned(self) -> Pen {
+        match self {
+            Pen::Dash | Pen::Round => Pen::Thin,
+            p => p,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Direction {
+    Up,
+    Right,
+    Down,
+    Left,
+}
+
+// Which way a diagonal stroke goes, ╱ or ╲.
+#[derive(Copy, Clone, Debug)]
+pub enum Slant {
+    Rise,
+    Fall,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FieldDrawing {
+    pub up: Pen,
+    pub right: Pen,
+    pub down: Pen,
+    pub left: Pen,
+
+    // Diagonals go corner to corner.  Box drawing only has thin ones,
+    // which don't join the other arms, so whatever the pen, they are
+    // drawn thin, and only if there are no other arms.
+    pub rise: Pen,
+    pub fall: Pen,
+}
+
+impl FieldDrawing {
+    pub fn new_from(d: Direction, p: Pen) -> FieldDrawing {
+        let mut up = Pen::None;
+        let mut right = Pen::None;
+        let mut down = Pen::None;
+        let mut left = Pen::None;
+
+        match d {
+            Direction::Up    => up = p,
+            Direction::Right => right = p,
+            Direction::Down  => down = p,
+            Direction::Left  => left = p,
+        }
+
+        FieldDrawing {up, right, down, left, rise: Pen::None, fall: Pen::None}
+    }
+
+    fn new_slant(s: Slant, p: Pen) -> FieldDrawing {
+        let (rise, fall) = match s {
+            Slant::Rise => (p, Pen::None),
+            Slant::Fall => (Pen::None, p),
+        };
+
+        FieldDrawing {up: Pen::None, right: Pen::None, down: Pen::None, left: Pen::None,
+                      rise, fall}
+    }
+
+    fn combine(&self, other: FieldDrawing) -> FieldDrawing {
+        FieldDrawing {up:    Pen::combine(self.up, other.up),
+                      right: Pen::combine(self.right, other.right),
+                      down:  Pen::combine(self.down, other.down),
+                      left:  Pen::combine(self.left, other.left),
+                      rise:  Pen::combine(self.rise, other.rise),
+                      fall:  Pen::combine(self.fall, other.fall)}
+    }
+
+    // The diagonals on their own, if that's all there is.
+    fn slant(&self) -> Option<(bool, bool)> {
+        let is = |p: Pen| !matches!(p, Pen::None);
+        match (is(self.up), is(self.right), is(self.down), is(self.left)) {
+            (false, false, false, false) if is(self.rise) || is(self.fall)
+                => Some((is(self.rise), is(self.fall))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Field {
+    None,
+    Decoration(char),
+    Drawing(FieldDrawing),
+}
+
+#[derive(Debug)]
+pub struct Grid {
+    pub w: i16,
+    pub h: i16,
+
+    grid: Vec<Field>,
+
+    // Color pair of each field, 0 for the default colors.
+    colors: Vec<i16>,
+}
+
+impl Grid {
+    // The fields sit where the lines of a w by h grid cross, so there
+    // are always (w + 1) * (h + 1) of them.  A 0x0 grid is thus one
+    // field that can be drawn on, with no cells around it for loops
+    // that go 0..w to visit.  Negative sizes are a bug.
+    pub fn new(w: i16, h: i16) -> Grid {
+        assert!(w >= 0 && h >= 0, "a grid can't be {}x{}", w, h);
+
+        let mut grid = Vec::new();
+        for _ in 0.. (w + 1) * (h + 1) {
+            grid.push(Field::None);
+        }
+
+        let colors = vec![0; grid.len()];
+        Grid {w, h, grid, colors}
+    }
+
+    fn field_idx(&self, x: i16, y: i16) -> usize {
+        y as usize * (self.w + 1) as usize + x as usize
+    }
+
+    // Like field_idx, but None off the grid.  Everything that paints
+    // or reads fields goes through these, so that whatever sticks out
+    // of the grid, like a spill at the edge of the board, is cut off.
+    fn checked_idx(&self, x: i16, y: i16) -> Option<usize> {
+        if x < 0 || y < 0 || x > self.w || y > self.h {
+            None
+        } else {
+            Some(self.field_idx(x, y))
+        }
+    }
+
+    fn checked_field_mut(&mut self, x: i16, y: i16) -> Option<&mut Field> {
+        match self.checked_idx(x, y) {
+            Some(idx) => Some(&mut self.grid[idx]),
+            None => None,
+        }
+    }
+
+    pub fn paint(&mut self, x: i16, y: i16, d: Direction, p: Pen) {
+        self.paint_drawing(x, y, FieldDrawing::new_from(d, p));
+    }
+
+    fn paint_drawing(&mut self, x: i16, y: i16, dw2: FieldDrawing) {
+        let idx = match self.checked_idx(x, y) {
+            Some(idx) => idx,
+            None => return,
+        };
+        self.colors[idx] = 0;
+        let f = &mut self.grid[idx];
+        *f = match *f {
+            Field::None |
+            Field::Decoration(..)
+                => Field::Drawing(dw2),
+
+            Field::Drawing(dw)
+                => Field::Drawing(dw.combine(dw2)),
+        }
+    }
+
+    // Draw a diagonal of len fields, starting at x0, y0 and going
+    // right.
+    pub fn paint_slant(&mut self, x0: i16, y0: i16, len: i16, s: Slant, p: Pen) {
+        assert!(len >= 0);
+
+        let dy = match s {
+            Slant::Rise => -1,
+            Slant::Fall => 1,
+        };
+        for i in 0..len {
+            self.paint_drawing(x0 + i, y0 + i * dy, FieldDrawing::new_slant(s, p));
+        }
+    }
+
+    pub fn clear(&mut self, x: i16, y: i16, w: i16, h: i16) {
+        assert!(w >= 0);
+        assert!(h >= 0);
+
+        // Inner portion can be wiped simply.
+        for xx in x .. x+w {
+            for yy in y .. y+h {
+                // Left or right edge.
+                let ex0 = xx == x;
+                let ex1 = xx == x+w-1;
+                let ex = ex0 || ex1;
+
+                // Upper or lower edge.
+                let ey0 = yy == y;
+                let ey1 = yy == y+h-1;
+                let ey = ey0 || ey1;
+
+                let f = match self.checked_field_mut(xx, yy) {
+                    Some(f) => f,
+                    None => continue,
+                };
+
+                if !ex && !ey {
+                    // Non-edge tile.
+                    *f = Field::None;
+                } else {
+                    if ex {
+                        if let Field::Drawing(ref mut dw) = *f {
+                            // Diagonals reach inside from any edge.
+                            dw.rise = Pen::None;
+                            dw.fall = Pen::None;
+
+                            if !ey {
+                                dw.down = Pen::None;
+                                dw.up = Pen::None;
+                            }
+
+                            if ex0 {
+                                dw.right = Pen::None;
+                            } else {
+                                dw.left = Pen::None;
+                            }
+                        } else {
+                            *f = Field::None;
+                        }
+                    }
+
+                    if ey {
+                        // Upper or lower edge.  We erase the
+                        // horizontal and the inner (down or up) arm.
+                        if let Field::Drawing(ref mut dw) = *f {
+                            dw.rise = Pen::None;
+                            dw.fall = Pen::None;
+
+                            if !ex {
+                                dw.left = Pen::None;
+                                dw.right = Pen::None;
+                            }
+
+                            if ey0 {
+                                dw.down = Pen::None;
+                            } else {
+                                dw.up = Pen::None;
+                            }
+                        } else {
+                            *f = Field::None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: i16, y: i16, w: i16, h: i16, f: Field) {
+        assert!(w >= 0);
+        assert!(h >= 0);
+
+        for xx in x .. x+w {
+            for yy in y .. y+h {
+                if let Some(field) = self.checked_field_mut(xx, yy) {
+                    *field = f;
+                }
+            }
+        }
+        self.color_rect(x, y, w, h, 0);
+    }
+
+    // Fields keep their color until something else is painted over
+    // them.
+    pub fn color_rect(&mut self, x: i16, y: i16, w: i16, h: i16, color: i16) {
+        assert!(w >= 0);
+        assert!(h >= 0);
+
+        for xx in x .. x+w {
+            for yy in y .. y+h {
+                if let Some(idx) = self.checked_idx(xx, yy) {
+                    self.colors[idx] = color;
+                }
+            }
+        }
+    }
+
+    // Like color_rect, but only for the fields with walls in them.
+    pub fn color_drawings(&mut self, x: i16, y: i16, w: i16, h: i16, color: i16) {
+        assert!(w >= 0);
+        assert!(h >= 0);
+
+        for xx in x .. x+w {
+            for yy in y .. y+h {
+                if let Some(idx) = self.checked_idx(xx, yy) {
+                    if let Field::Drawing(..) = self.grid[idx] {
+                        self.colors[idx] = color;
+                    }
+                }
+            }
+        }
+    }
+
+    // Draw outline of the same w x h fields that fill_rect and clear
+    // work with.
+    pub fn draw_rect(&mut self, x: i16, y: i16, w: i16, h: i16, p: Pen) {
+        assert!(w >= 1);
+        assert!(h >= 1);
+
+        self.paint_wall(x, y, w - 1, Direction::Right, true, p);
+        self.paint_wall(x, y + h - 1, w - 1, Direction::Right, true, p);
+        self.paint_wall(x, y, h - 1, Direction::Down, true, p);
+        self.paint_wall(x + w - 1, y, h - 1, Direction::Down, true, p);
+    }
+
+    pub fn paint_decoration(&mut self, x: i16, y: i16, s: &str) {
+        for (n, c) in s.chars().enumerate() {
+            if c != '\0' {
+                if let Some(idx) = self.checked_idx(x + n as i16, y) {
+                    self.grid[idx] = Field::Decoration(c);
+                    self.colors[idx] = 0;
+                }
+            }
+        }
+    }
+
+    pub fn paint_wall(&mut self, x0: i16, y0: i16, len: i16,
+                  d: Direction, inclusive: bool, p: Pen) {
+        assert!(len >= 0);
+
+        let (d1, d2, dx, dy) = match d {
+            Direction::Right => (Direction::Right, Direction::Left, 1, 0),
+            Direction::Left => (Direction::Left, Direction::Right, -1, 0),
+            Direction::Down => (Direction::Down, Direction::Up, 0, 1),
+            Direction::Up => (Direction::Up, Direction::Down, 0, -1),
+        };
+
+        let x1 = x0 + len * dx;
+        let y1 = y0 + len * dy;
+
+        if inclusive {
+            self.paint(x0, y0, d1, p);
+        }
+        for i in 1..len {
+            let xx = x0 + i * dx;
+            let yy = y0 + i * dy;
+            self.paint(xx, yy, d2, p);
+            self.paint(xx, yy, d1, p);
+        }
+        if inclusive {
+            self.paint(x1, y1, d2, p);
+        }
+    }
+
+    // ASCII has no box drawing, so all that remains of a drawing is
+    // whether it's a line or a junction.
+    fn render_field_drawing_ascii(dw: FieldDrawing) -> &'static str {
+        match dw.slant() {
+            Some((true, false)) => return "/",
+            Some((false, true)) => return "\\",
+            Some(_) => return "X",
+            None => {},
+        }
+
+        let is = |p: Pen| !matches!(p, Pen::None);
+        match (is(dw.up), is(dw.right), is(dw.down), is(dw.left)) {
+            (false, false, false, false) => " ",
+            (false, _, false, _) => "-",
+            (_, false, _, false) => "|",
+            _ => "+",
+        }
+    }
+
+    fn render_field_drawing(dw: FieldDrawing) -> &'static str {
+        match dw.slant() {
+            Some((true, false)) => return "╱",
+            Some((false, true)) => return "╲",
+            Some(_) => return "╳",
+            None => {},
+        }
+
+        match (dw.up, dw.right, dw.down, dw.left) {
+            (Pen::None, Pen::Dash, Pen::None, Pen::Dash) => "╌",
+            (Pen::Dash, Pen::None, Pen::Dash, Pen::None) => "╎",
+            (Pen::None, Pen::Round, Pen::Round, Pen::None) => "╭",
+            (Pen::None, Pen::None, Pen::Round, Pen::Round) => "╮",
+            (Pen::Round, Pen::Round, Pen::None, Pen::None) => "╰",
+            (Pen::Round, Pen::None, Pen::None, Pen::Round) => "╯",
+
+            (Pen::Dash, _, _, _) | (_, Pen::Dash, _, _) |
+            (_, _, Pen::Dash, _) | (_, _, _, Pen::Dash) |
+            (Pen::Round, _, _, _) | (_, Pen::Round, _, _) |
+            (_, _, Pen::Round, _) | (_, _, _, Pen::Round)
+                => Grid::render_field_drawing(
+                    FieldDrawing {up: dw.up.thinned(),
+                                  right: dw.right.thinned(),
+                                  down: dw.down.thinned(),
+                                  left: dw.left.thinned(),
+                                  ..dw}),
+
+            (Pen::None, Pen::None, Pen::None, Pen::None) => " ",
+
+            (Pen::None, Pen::None, Pen::None, Pen::Thin) => "╴",
+            (Pen::None, Pen::None, Pen::Thin, Pen::None) => "╷",
+            (Pen::None, Pen::None, Pen::Thin, Pen::Thin) => "┐",
+            (Pen::None, Pen::Thin, Pen::None, Pen::None) => "╶",
+            (Pen::None, Pen::Thin, Pen::None, Pen::Thin) => "─",
+            (Pen::None, Pen::Thin, Pen::Thin, Pen::None) => "┌",
+            (Pen::None, Pen::Thin, Pen::Thin, Pen::Thin) => "┬",
+            (Pen::Thin, Pen::None, Pen::None, Pen::None) => "╵",
+            (Pen::Thin, Pen::None, Pen::None, Pen::Thin) => "┘",
+            (Pen::Thin, Pen::None, Pen::Thin, Pen::None) => "│",
+            (Pen::Thin, Pen::None, Pen::Thin, Pen::Thin) => "┤",
+            (Pen::Thin, Pen::Thin, Pen::None, Pen::None) => "└",
+            (Pen::Thin, Pen::Thin, Pen::None, Pen::Thin) => "┴",
+            (Pen::Thin, Pen::Thin, Pen::Thin, Pen::None) => "├",
+            (Pen::Thin, Pen::Thin, Pen::Thin, Pen::Thin) => "┼",
+
+            (Pen::None, Pen::None, Pen::None, Pen::Thik) => "╸",
+            (Pen::None, Pen::None, Pen::Thik, Pen::None) => "╻",
+            (Pen::None, Pen::None, Pen::Thik, Pen::Thik) => "┓",
+            (Pen::None, Pen::Thik, Pen::None, Pen::None) => "╺",
+            (Pen::None, Pen::Thik, Pen::None, Pen::Thik) => "━",
+            (Pen::None, Pen::Thik, Pen::Thik, Pen::None) => "┏",
+            (Pen::None, Pen::Thik, Pen::Thik, Pen::Thik) => "┳",
+            (Pen::Thik, Pen::None, Pen::None, Pen::None) => "╹",
+            (Pen::Thik, Pen::None, Pen::None, Pen::Thik) => "┛",
+            (Pen::Thik, Pen::None, Pen::Thik, Pen::None) => "┃",
+            (Pen::Thik, Pen::None, Pen::Thik, Pen::Thik) => "┫",
+            (Pen::Thik, Pen::Thik, Pen::None, Pen::None) => "┗",
+            (Pen::Thik, Pen::Thik, Pen::None, Pen::Thik) => "┻",
+            (Pen::Thik, Pen::Thik, Pen::Thik, Pen::None) => "┣",
+            (Pen::Thik, Pen::Thik, Pen::Thik, Pen::Thik) => "╋",
+
+            (Pen::None, Pen::None, Pen::Thik, Pen::Thin) => "┒",
+            (Pen::None, Pen::None, Pen::Thin, Pen::Thik) => "┑",
+            (Pen::None, Pen::Thik, Pen::None, Pen::Thin) => "╼",
+            (Pen::None, Pen::Thik, Pen::Thik, Pen::Thin) => "┲",
+            (Pen::None, Pen::Thik, Pen::Thin, Pen::None) => "┍",
+            (Pen::None, Pen::Thik, Pen::Thin, Pen::Thik) => "┯",
+            (Pen::None, Pen::Thik, Pen::Thin, Pen::Thin) => "┮",
+            (Pen::None, Pen::Thin, Pen::None, Pen::Thik) => "╾",
+            (Pen::None, Pen::Thin, Pen::Thik, Pen::None) => "┎",
+            (Pen::None, Pen::Thin, Pen::Thik, Pen::Thik) => "┱",
+            (Pen::None, Pen::Thin, Pen::Thik, Pen::Thin) => "┰",
+            (Pen::None, Pen::Thin, Pen::Thin, Pen::Thik) => "┭",
+            (Pen::Thik, Pen::None, Pen::None, Pen::Thin) => "┚",
+            (Pen::Thik, Pen::None, Pen::Thik, Pen::Thin) => "┨",
+            (Pen::Thik, Pen::None, Pen::Thin, Pen::None) => "╿",
+            (Pen::Thik, Pen::None, Pen::Thin, Pen::Thik) => "┩",
+            (Pen::Thik, Pen::None, Pen::Thin, Pen::Thin) => "┦",
+            (Pen::Thik, Pen::Thik, Pen::None, Pen::Thin) => "┺",
+            (Pen::Thik, Pen::Thik, Pen::Thik, Pen::Thin) => "╊",
+            (Pen::Thik, Pen::Thik, Pen::Thin, Pen::None) => "┡",
+            (Pen::Thik, Pen::Thik, Pen::Thin, Pen::Thik) => "╇",
+            (Pen::Thik, Pen::Thik, Pen::Thin, Pen::Thin) => "╄",
+            (Pen::Thik, Pen::Thin, Pen::None, Pen::None) => "┖",
+            (Pen::Thik, Pen::Thin, Pen::None, Pen::Thik) => "┹",
+            (Pen::Thik, Pen::Thin, Pen::None, Pen::Thin) => "┸",
+            (Pen::Thik, Pen::Thin, Pen::Thik, Pen::None) => "┠",
+            (Pen::Thik, Pen::Thin, Pen::Thik, Pen::Thik) => "╉",
+            (Pen::Thik, Pen::Thin, Pen::Thik, Pen::Thin) => "╂",
+            (Pen::Thik, Pen::Thin, Pen::Thin, Pen::None) => "┞",
+            (Pen::Thik, Pen::Thin, Pen::Thin, Pen::Thik) => "╃",
+            (Pen::Thik, Pen::Thin, Pen::Thin, Pen::Thin) => "╀",
+            (Pen::Thin, Pen::None, Pen::None, Pen::Thik) => "┙",
+            (Pen::Thin, Pen::None, Pen::Thik, Pen::None) => "╽",
+            (Pen::Thin, Pen::None, Pen::Thik, Pen::Thik) => "┪",
+            (Pen::Thin, Pen::None, Pen::Thik, Pen::Thin) => "┧",
+            (Pen::Thin, Pen::None, Pen::Thin, Pen::Thik) => "┥",
+            (Pen::Thin, Pen::Thik, Pen::None, Pen::None) => "┕",
+            (Pen::Thin, Pen::Thik, Pen::None, Pen::Thik) => "┷",
+            (Pen::Thin, Pen::Thik, Pen::None, Pen::Thin) => "┶",
+            (Pen::Thin, Pen::Thik, Pen::Thik, Pen::None) => "┢",
+            (Pen::Thin, Pen::Thik, Pen::Thik, Pen::Thik) => "╈",
+            (Pen::Thin, Pen::Thik, Pen::Thin, Pen::Thin) => "┾",
+            (Pen::Thin, Pen::Thik, Pen::Thik, Pen::Thin) => "╆",
+            (Pen::Thin, Pen::Thik, Pen::Thin, Pen::None) => "┝",
+            (Pen::Thin, Pen::Thik, Pen::Thin, Pen::Thik) => "┿",
+            (Pen::Thin, Pen::Thin, Pen::None, Pen::Thik) => "┵",
+            (Pen::Thin, Pen::Thin, Pen::Thik, Pen::None) => "┟",
+            (Pen::Thin, Pen::Thin, Pen::Thik, Pen::Thik) => "╅",
+            (Pen::Thin, Pen::Thin, Pen::Thik, Pen::Thin) => "╁",
+            (Pen::Thin, Pen::Thin, Pen::Thin, Pen::Thik) => "┽",
+        }
+    }
+
+    // The character shown at the given field, or None if the field
+    // is transparent.  Fields off the grid are transparent.
+    pub fn field_char(&self, x: i16, y: i16, ascii: bool) -> Option<char> {
+        let idx = self.checked_idx(x, y)?;
+        match self.grid[idx] {
+            Field::None | Field::Decoration('\0') => None,
+            Field::Decoration(c) => Some(c),
+            Field::Drawing(dw) => if ascii {
+                Grid::render_field_drawing_ascii(dw)
+            } else {
+                Grid::render_field_drawing(dw)
+            }.chars().next(),
+        }
+    }
+
+    // Color pair of the field at x, y, 0 for the default colors and
+    // off the grid.
+    pub fn color(&self, x: i16, y: i16) -> i16 {
+        self.checked_idx(x, y).map_or(0, |idx| self.colors[idx])
+    }
+}
+
+// The grid as render would draw it, with transparent fields shown as
+// spaces and a newline after each row.
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in 0..self.h+1 {
+            for x in 0..self.w+1 {
+                write!(f, "{}", self.field_char(x, y, false).unwrap_or(' '))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// How many shapes blocks come in, see Block::shape.
+pub const SHAPES: usize = 7;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LiquidType {
+    Acid,
+    Glue,
+}
+
+impl LiquidType {
+    // Where the liquid of an exploded flask ends up, relative to the
+    // flask.  Acid runs farther than glue.
+    pub fn spill_shape(&self) -> &'static [(i16, i16)] {
+        match *self {
+            LiquidType::Acid => &[(0, 0),
+                                  (0, 1), (1, 0), (0, -1), (-1, 0),
+                                  (0, 2), (2, 0), (0, -2), (-2, 0),
+                                  (1, 1), (1, -1), (-1, -1), (-1, 1)],
+            LiquidType::Glue => &[(0, 0), (0, 1), (1, 0), (0, -1), (-1, 0)],
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TileType {
+    Plain(u8),
+    Permanent,
+    Killer(u8),
+    Picker,
+    Centerpiece(u8),
+    Whopper(u8),
+    Flask(LiquidType),
+    Spillage(LiquidType),
+    Plus,
+    Minus,
+    Bomb,
+    Clock,
+    Chaos,
+    Converter,
+    Hourglass,
+    Meteor,
+}
+
+// How much time an exploded clock gives for placing the block.
+pub const CLOCK_MS: i64 = 5000;
+
+#[derive(PartialEq,Debug)]
+pub enum ExplodeAction {
+    Remove,
+    Convert(TileType),
+    Spill(LiquidType),
+    Plus,
+    Minus,
+    Clear,
+
+    // Milliseconds to put back on the drop gauge.
+    AddTime(i64),
+
+    // Shuffle the tiles that are left, see Block::explode.
+    Shuffle,
+
+    // Turn the tiles around into plain ones, see Block::explode.
+    ConvertNeighbors,
+
+    // Slow the gauges down for a while.
+    SlowDown,
+
+    // Rain tiles down on the playground.
+    Meteor,
+    Complex(Box<ExplodeAction>, Box<ExplodeAction>),
+}
+
+// What Block::explode did.
+#[derive(PartialEq, Debug)]
+pub struct Explosion {
+    pub exploded: Vec<(i16, i16, TileType)>,
+
+    // The points that the exploded tiles give.
+    pub hits: u32,
+
+    // The change to the multiplier.
+    pub dmult: i32,
+
+    // Milliseconds that clocks put back on the drop gauge.
+    pub time: i64,
+
+    // Whether an hourglass slows the gauges down.
+    pub slowdown: bool,
+
+    // How many meteors rain tiles down.
+    pub meteors: u32,
+}
+
+impl TileType {
+    pub fn new_random<R: Rng>(lvl: u8, rng: &mut R) -> TileType {
+        // Draws of tiles that aren't available at this level are
+        // retried.  That normally ends quickly, but should the
+        // always-available tiles ever be ruled out, give up and fall
+        // back to a plain tile instead of spinning forever.
+        const MAX_DRAWS: u32 = 1000;
+
+        for _ in 0..MAX_DRAWS {
+            match rng.gen_range(0, 39) {
+                0..=20 => return TileType::Plain(0),
+
+                21..=23 => return TileType::Picker,
+
+                24 if lvl >= 1
+                    => return if rng.gen() { TileType::Minus }
+                              else { TileType::Plus },
+
+                25..=26 if lvl >= 2
+                    => return TileType::Plain(1 + rng.gen_range(0, lvl)),
+
+                27 if lvl >= 3
+                    => return TileType::Flask(if rng.gen() { LiquidType::Acid }
+                                              else { LiquidType::Glue }),
+
+                28 if lvl >= 4
+                    => return TileType::Killer(1 + rng.gen_range(0, lvl / 8 + 1)),
+
+
+                29..=30 if lvl >= 5
+                    => return TileType::Centerpiece(1 + rng.gen_range(0, lvl / 4 + 1)),
+
+                31 if lvl >= 6
+                    => return TileType::Whopper(1 + rng.gen_range(0, lvl / 4 + 1)),
+
+                32 if lvl >= 8 && rng.gen()
+                    => return TileType::Permanent,
+
+                33 if lvl >= 7 && rng.gen()
+                    => return TileType::Bomb,
+
+                34 if lvl >= 5 && rng.gen()
+                    => return TileType::Clock,
+
+                35 if lvl >= 10 && rng.gen()
+                    => return TileType::Chaos,
+
+                36 if lvl >= 9 && rng.gen()
+                    => return TileType::Converter,
+
+                37 if lvl >= 12 && rng.gen_range(0, 3) == 0
+                    => return TileType::Hourglass,
+
+                38 if lvl >= 14 && rng.gen_range(0, 3) == 0
+                    => return TileType::Meteor,
+
+                _ => {},
+            }
+        }
+
+        TileType::Plain(0)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TileType::Plain(0)                => "plain",
+            TileType::Plain(_)                => "shield",
+            TileType::Permanent               => "permanent",
+            TileType::Killer(_)               => "killer",
+            TileType::Picker                  => "picker",
+            TileType::Centerpiece(_)          => "centerpiece",
+            TileType::Whopper(_)              => "whopper",
+            TileType::Flask(LiquidType::Glue) => "glue flask",
+            TileType::Flask(LiquidType::Acid) => "acid flask",
+            TileType::Spillage(_)             => "spill",
+            TileType::Plus                    => "plus",
+            TileType::Minus                   => "minus",
+            TileType::Bomb                    => "bomb",
+            TileType::Clock                   => "clock",
+            TileType::Chaos                   => "chaos",
+            TileType::Converter               => "converter",
+            TileType::Hourglass               => "hourglass",
+            TileType::Meteor                  => "meteor",
+        }
+    }
+
+    pub fn render(&self) -> &'static str {
+        match *self {
+            TileType::Permanent               => " ✖ ",
+            TileType::Picker                  => "[ ]",
+            TileType::Flask(LiquidType::Glue) => " ▿ ",
+            TileType::Flask(LiquidType::Acid) => " ▴ ",
+            TileType::Plus                    => " + ",
+            TileType::Minus                   => " - ",
+            TileType::Bomb                    => " ✸ ",
+            TileType::Clock                   => " ◷ ",
+            TileType::Chaos                   => " ⁇ ",
+            TileType::Converter               => " ⊙ ",
+            TileType::Hourglass               => " ½ ",
+            TileType::Meteor                  => " ☄ ",
+
+            TileType::Plain(n) => match n {
+                0 => "   ",
+                1 => " • ",
+                2 => " •²",
+                3 => " •³",
+                4 => " •⁴",
+                5 => " •⁵",
+                6 => " •⁶",
+                7 => " •⁷",
+                8 => " •⁸",
+                9 => " •⁹",
+                _ => " •ⁿ",
+            },
+
+            TileType::Killer(n) => match n {
+                0 => " ↯⁰",
+                1 => " ↯ ",
+                2 => " ↯²",
+                3 => " ↯³",
+                4 => " ↯⁴",
+                5 => " ↯⁵",
+                6 => " ↯⁶",
+                7 => " ↯⁷",
+                8 => " ↯⁸",
+                9 => " ↯⁹",
+                _ => " ↯ⁿ",
+            },
+
+            TileType::Centerpiece(n) => match n {
+                0 => " ◉⁰",
+                1 => " ◉ ",
+                2 => " ◉²",
+                3 => " ◉³",
+                4 => " ◉⁴",
+                5 => " ◉⁵",
+                6 => " ◉⁶",
+                7 => " ◉⁷",
+                8 => " ◉⁸",
+                9 => " ◉⁹",
+                _ => " ◉ⁿ",
+            },
+
+            TileType::Whopper(n) => match n {
+                0 => " ✱⁰",
+                1 => " ✱ ",
+                2 => " ✱²",
+                3 => " ✱³",
+                4 => " ✱⁴",
+                5 => " ✱⁵",
+                6 => " ✱⁶",
+                7 => " ✱⁷",
+                8 => " ✱⁸",
+                9 => " ✱⁹",
+                _ => " ✱ⁿ",
+            },
+
+            // Spills are formatted differently.
+            TileType::Spillage(LiquidType::Glue) => "▿",
+            TileType::Spillage(LiquidType::Acid) => "▴",
+        }
+    }
+
+    // The tile in board maps, see Block::from_map.  Plain tiles have
+    // their number written as a digit, other tiles are taken to have
+    // number 1.
+    pub fn from_glyph(c: char) -> Option<TileType> {
+        Some(match c {
+            '*' => TileType::Plain(0),
+            '1'..='9' => TileType::Plain(c as u8 - b'0'),
+            '#' => TileType::Permanent,
+            'X' => TileType::Killer(1),
+            'P' => TileType::Picker,
+            'C' => TileType::Centerpiece(1),
+            'W' => TileType::Whopper(1),
+            'g' => TileType::Flask(LiquidType::Glue),
+            'a' => TileType::Flask(LiquidType::Acid),
+            '~' => TileType::Spillage(LiquidType::Glue),
+            '%' => TileType::Spillage(LiquidType::Acid),
+            '+' => TileType::Plus,
+            '-' => TileType::Minus,
+            'B' => TileType::Bomb,
+            'T' => TileType::Clock,
+            '?' => TileType::Chaos,
+            'V' => TileType::Converter,
+            'H' => TileType::Hourglass,
+            'M' => TileType::Meteor,
+            _ => return None,
+        })
+    }
+
+    pub fn glyph(&self) -> char {
+        match *self {
+            TileType::Plain(n @ 1..=9)           => (b'0' + n) as char,
+            TileType::Plain(_)                   => '*',
+            TileType::Permanent                  => '#',
+            TileType::Killer(_)                  => 'X',
+            TileType::Picker                     => 'P',
+            TileType::Centerpiece(_)             => 'C',
+            TileType::Whopper(_)                 => 'W',
+            TileType::Flask(LiquidType::Glue)    => 'g',
+            TileType::Flask(LiquidType::Acid)    => 'a',
+            TileType::Spillage(LiquidType::Glue) => '~',
+            TileType::Spillage(LiquidType::Acid) => '%',
+            TileType::Plus                       => '+',
+            TileType::Minus                      => '-',
+            TileType::Bomb                       => 'B',
+            TileType::Clock                      => 'T',
+            TileType::Chaos                      => '?',
+            TileType::Converter                  => 'V',
+            TileType::Hourglass                  => 'H',
+            TileType::Meteor                     => 'M',
+        }
+    }
+
+    // The glyph, followed by the strength for tiles that have one.
+    // Unlike glyphs alone, codes tell all tiles apart.
+    pub fn code(&self) -> String {
+        match *self {
+            TileType::Plain(n) => format!("*{}", n),
+            TileType::Killer(n) | TileType::Centerpiece(n)
+                | TileType::Whopper(n) => format!("{}{}", self.glyph(), n),
+            _ => self.glyph().to_string(),
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<TileType> {
+        let mut chars = code.chars();
+        let c = chars.next()?;
+        let rest = chars.as_str();
+        if rest.is_empty() {
+            return TileType::from_glyph(c);
+        }
+
+        let n = rest.parse().ok()?;
+        Some(match c {
+            '*' => TileType::Plain(n),
+            'X' => TileType::Killer(n),
+            'C' => TileType::Centerpiece(n),
+            'W' => TileType::Whopper(n),
+            _ => return None,
+        })
+    }
+
+    // Like render, but with a shape of its own for each family of
+    // tiles, so that they can be told apart without the colors.  The
+    // numbers are as in render.
+    pub fn render_distinct(&self) -> String {
+        let (c, n) = match *self {
+            TileType::Permanent               => return "▓▓▓".to_string(),
+            TileType::Picker                  => return "[ ]".to_string(),
+            TileType::Flask(LiquidType::Glue) => return "(≈)".to_string(),
+            TileType::Flask(LiquidType::Acid) => return "(∴)".to_string(),
+            TileType::Plus                    => return " ⊕ ".to_string(),
+            TileType::Minus                   => return " ⊖ ".to_string(),
+            TileType::Bomb                    => return " ✸ ".to_string(),
+            TileType::Clock                   => return " ◷ ".to_string(),
+            TileType::Chaos                   => return " ⁇ ".to_string(),
+            TileType::Converter               => return " ⊙ ".to_string(),
+            TileType::Hourglass               => return " ½ ".to_string(),
+            TileType::Meteor                  => return " ☄ ".to_string(),
+            TileType::Plain(0)                => return "   ".to_string(),
+
+            TileType::Plain(n)       => ('○', n),
+            TileType::Killer(n)      => ('✕', n),
+            TileType::Centerpiece(n) => ('◆', n),
+            TileType::Whopper(n)     => ('★', n),
+
+            // Spills are formatted differently.
+            TileType::Spillage(LiquidType::Glue) => return "≈".to_string(),
+            TileType::Spillage(LiquidType::Acid) => return "∴".to_string(),
+        };
+
+        const SUPERSCRIPTS: [char; 10] = ['⁰', ' ', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+        let sup = SUPERSCRIPTS.get(n as usize).cloned().unwrap_or('ⁿ');
+        format!(" {}{}", c, sup)
+    }
+
+    // Like render, but for terminals that only do ASCII.
+    pub fn render_ascii(&self) -> String {
+        let (c, n) = match *self {
+            TileType::Permanent               => return " # ".to_string(),
+            TileType::Picker                  => return "[ ]".to_string(),
+            TileType::Flask(LiquidType::Glue) => return " v ".to_string(),
+            TileType::Flask(LiquidType::Acid) => return " ^ ".to_string(),
+            TileType::Plus                    => return " + ".to_string(),
+            TileType::Minus                   => return " - ".to_string(),
+            TileType::Bomb                    => return " B ".to_string(),
+            TileType::Clock                   => return " T ".to_string(),
+            TileType::Chaos                   => return " ? ".to_string(),
+            TileType::Converter               => return " V ".to_string(),
+            TileType::Hourglass               => return " H ".to_string(),
+            TileType::Meteor                  => return " M ".to_string(),
+            TileType::Plain(0)                => return "   ".to_string(),
+
+            TileType::Plain(n)       => ('*', n),
+            TileType::Killer(n)      => ('X', n),
+            TileType::Centerpiece(n) => ('o', n),
+            TileType::Whopper(n)     => ('@', n),
+
+            TileType::Spillage(LiquidType::Glue) => return "v".to_string(),
+            TileType::Spillage(LiquidType::Acid) => return "^".to_string(),
+        };
+
+        match n {
+            1 => format!(" {} ", c),
+            0..=9 => format!(" {}{}", c, n),
+            _ => format!(" {}n", c),
+        }
+    }
+
+    pub fn drop(&self) -> Option<TileType> {
+        match *self {
+            TileType::Killer(_) => Some(TileType::Plain(0)),
+            tt => Some(tt),
+        }
+    }
+
+    pub fn explode(&self) -> ExplodeAction {
+        use ExplodeAction::*;
+        match *self {
+            TileType::Plain(0) => Remove,
+            TileType::Plain(n) => Convert(TileType::Plain(n - 1)),
+
+            TileType::Centerpiece(1) => Remove,
+            TileType::Centerpiece(n) => Convert(TileType::Centerpiece(n - 1)),
+
+            TileType::Whopper(n) => Convert(TileType::Centerpiece(n)),
+
+            TileType::Flask(liquid) => Spill(liquid),
+
+            TileType::Plus => Complex(Box::new(Remove), Box::new(Plus)),
+            TileType::Minus => Complex(Box::new(Remove), Box::new(Minus)),
+            TileType::Bomb => Complex(Box::new(Remove), Box::new(Clear)),
+            TileType::Clock => Complex(Box::new(Remove), Box::new(AddTime(CLOCK_MS))),
+            TileType::Chaos => Complex(Box::new(Remove), Box::new(Shuffle)),
+            TileType::Converter => Complex(Box::new(Remove), Box::new(ConvertNeighbors)),
+            TileType::Hourglass => Complex(Box::new(Remove), Box::new(SlowDown)),
+            TileType::Meteor => Complex(Box::new(Remove), Box::new(Meteor)),
+
+            _ => Remove,
+        }
+    }
+
+    // What the tile does, as told on the help screen.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            TileType::Plain(0) =>
+                "Plain tiles.  When organized\ninto a 3x3, explode and\n\
+                 disappear.  1 point.",
+            TileType::Plain(_) =>
+                "Shield tiles.  When exploded,\ndecrease the number, eventually\n\
+                 change to plain.  n+1 points.",
+            TileType::Centerpiece(_) =>
+                "Centerpiece.  Only explode\nwhen 3x3 has a centerpiece\n\
+                 in the center.  10*n points.",
+            TileType::Whopper(_) =>
+                "Whopper.  Like centerpiece\nbut only explodes 5x5.  When\n\
+                 exploded, changes to c-piece\nwith the same number.\n\
+                 30 points.",
+            TileType::Picker =>
+                "Picker.  Doesn't explode.\nAllows picking other tiles.",
+            TileType::Killer(_) =>
+                "Killer.  Kills tiles that\nit touches.  On drop,\nchanges to plain.",
+            TileType::Permanent =>
+                "Permanent.\nNever explodes.\nKill them!",
+            TileType::Plus | TileType::Minus =>
+                "Plus, Minus.  When exploded,\nchange the multiplier.\n\
+                 1 point.",
+            TileType::Flask(_) =>
+                "Flask with Glue and Acid.\nSpill contents around\n\
+                 when exploded.  1 point.\nAcid dissolves tiles moved\n\
+                 into it, glue holds them.",
+            TileType::Spillage(LiquidType::Glue) =>
+                "Spilled glue.  Holds\ntiles moved into it.",
+            TileType::Spillage(LiquidType::Acid) =>
+                "Spilled acid.  Dissolves\ntiles moved into it.",
+            TileType::Bomb =>
+                "Bomb.  When exploded, clears\nits row and column.",
+            TileType::Clock =>
+                "Clock.  When exploded, puts\nfive seconds back on the\n\
+                 drop gauge.",
+            TileType::Chaos =>
+                "Chaos.  When exploded,\nshuffles the tiles that\nare left.",
+            TileType::Converter =>
+                "Converter.  When exploded,\nturns the tiles next to it\n\
+                 into plain ones.",
+            TileType::Hourglass =>
+                "Hourglass.  When exploded,\nthe gauges run at half\n\
+                 speed for a while.",
+            TileType::Meteor =>
+                "Meteor.  When exploded,\nrains a few more tiles\n\
+                 down onto the board.",
+        }
+    }
+
+    pub fn is_plain(&self) -> bool {
+        matches!(*self,
+                 TileType::Plain(_) |
+                 TileType::Flask(..) |
+                 TileType::Plus |
+                 TileType::Minus |
+                 TileType::Bomb |
+                 TileType::Clock |
+                 TileType::Chaos |
+                 TileType::Converter |
+                 TileType::Hourglass |
+                 TileType::Meteor)
+    }
+
+    // The color pair of the tile family, see Theme.
+    pub fn color(&self) -> i16 {
+        match *self {
+            TileType::Plain(_) | TileType::Picker
+                | TileType::Converter             => 1,
+            TileType::Killer(_) | TileType::Bomb
+                | TileType::Chaos
+                | TileType::Meteor                => 2,
+            TileType::Centerpiece(_)              => 3,
+            TileType::Whopper(_)                  => 4,
+            TileType::Flask(_)
+                | TileType::Spillage(_)           => 5,
+            TileType::Plus | TileType::Minus
+                | TileType::Clock
+                | TileType::Hourglass             => 6,
+            TileType::Permanent                   => 7,
+        }
+    }
+
+    pub fn is_solid(&self) -> bool {
+        !matches!(*self, TileType::Spillage(_))
+    }
+
+    pub fn explodes(&self, tt2: TileType) -> bool {
+        match *self {
+            // Plain tiles explode other plain tiles (not e.g.
+            // Centerpieces).
+            tt1 if tt1.is_plain() => tt2.is_plain(),
+
+            // If Centerpieces is a centerpiece, it explodes other
+            // plain tiles or Centerpieces.
+            TileType::Centerpiece(_) => {
+                if let TileType::Centerpiece(_) = tt2 {
+                    true
+                } else {
+                    tt2.is_plain()
+                }
+            },
+
+            // If Whopper is a centerpiece, it explodes other plain
+            // tiles, Centerpieces and Whoppers.
+            TileType::Whopper(_) => {
+                match tt2 {
+                    TileType::Centerpiece(_) |
+                    TileType::Whopper(_) => true,
+                    _ => tt2.is_plain(),
+                }
+            },
+
+            // The rest doesn't explode.
+            _ => false,
+        }
+    }
+
+    pub fn collide(t1: TileType, t2: TileType) -> (Option<TileType>, Option<TileType>) {
+        match (t1, t2) {
+            // Liquids are never on the block.
+            (_, TileType::Spillage(LiquidType::Acid)) => (None, None),
+            (_, TileType::Spillage(LiquidType::Glue)) => (None, t1.drop()),
+
+            (TileType::Picker, _) => (t2.drop(), None),
+            (_, TileType::Picker) => (None, t1.drop()),
+
+            // When two killers meet, neither gets the upper hand,
+            // both lose one of their lives.
+            (TileType::Killer(m), TileType::Killer(n)) => {
+                let weaken = |n: u8| if n > 1 { Some(TileType::Killer(n - 1)) } else { None };
+                (weaken(m), weaken(n))
+            },
+
+            (TileType::Killer(1), _) => (None, None),
+            (TileType::Killer(n), _) => (Some(TileType::Killer(n - 1)), None),
+
+            (_, TileType::Killer(1)) => (None, None),
+            (_, TileType::Killer(n)) => (None, Some(TileType::Killer(n - 1))),
+
+            (m, n) => (Some(m), Some(n)),
+        }
+    }
+
+    pub fn collides(_t1: TileType, _t2: TileType) -> bool {
+        true
+    }
+
+    pub fn explode_shape(&self) -> &'static [(i16, i16)] {
+        match *self {
+            TileType::Whopper(_) => {
+                static SHAPE:[(i16, i16); 25] = [(-2, -2), (-1, -2), (0, -2), (1, -2), (2, -2),
+                                                 (-2, -1), (-1, -1), (0, -1), (1, -1), (2, -1),
+                                                 (-2,  0), (-1,  0), (0,  0), (1,  0), (2,  0),
+                                                 (-2,  1), (-1,  1), (0,  1), (1,  1), (2,  1),
+                                                 (-2,  2), (-1,  2), (0,  2), (1,  2), (2,  2)];
+                &SHAPE
+            },
+
+            _ => {
+                static SHAPE:[(i16, i16); 9] = [(-1, -1), (0, -1), (1, -1),
+                                                (-1,  0), (0,  0), (1,  0),
+                                                (-1,  1), (0,  1), (1,  1)];
+                &SHAPE
+            },
+        }
+    }
+
+    pub fn bonus(&self) -> u32 {
+        match *self {
+            TileType::Plain(n) => n as u32 + 1,
+            TileType::Centerpiece(n) => 10 * n as u32,
+            TileType::Whopper(_) => 30,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Block {
+    pub x: i16,
+    pub y: i16,
+    pub tiles: Vec<(i16, i16, TileType)>,
+}
+
+impl Block {
+    pub fn new() -> Block {
+        Block::new_at(0, 0)
+    }
+
+    pub fn new_at(x: i16, y: i16) -> Block {
+        Block {x, y, tiles:vec![]}
+    }
+
+    // Build a block from a map with a line per row and a character per
+    // tile, as given by TileType::glyph.  Dots and spaces are empty.
+    pub fn from_map(map: &str) -> Result<Block, String> {
+        let mut blk = Block::new();
+        for (y, line) in map.lines().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                if c == '.' || c == ' ' {
+                    continue;
+                }
+                match TileType::from_glyph(c) {
+                    Some(tt) => blk.tiles.push((x as i16, y as i16, tt)),
+                    None => return Err(format!("unknown tile '{}' at {}, {}", c, x, y)),
+                }
+            }
+        }
+        Ok(blk)
+    }
+
+    // The block as JSON on a single line, for saved games and bug
+    // reports: {"x":2,"y":2,"tiles":[[0,0,"*0"],[1,0,"X2"]]}, the
+    // tiles given by TileType::code.  Whatever reads it should ignore
+    // keys that it doesn't know, so that more can be added.
+    pub fn to_snapshot(&self) -> String {
+        self.to_json().to_string()
+    }
+
+    pub fn from_snapshot(text: &str) -> Result<Block, String> {
+        Block::from_json(&Json::parse(text)?).ok_or("not a block".to_string())
+    }
+
+    fn to_json(&self) -> Json {
+        let tiles = self.tiles.iter()
+            .map(|&(dx, dy, tt)| Json::List(vec![Json::Num(dx as i64), Json::Num(dy as i64),
+                                                 Json::Str(tt.code())]))
+            .collect();
+        Json::Obj(vec![("x".to_string(), Json::Num(self.x as i64)),
+                       ("y".to_string(), Json::Num(self.y as i64)),
+                       ("tiles".to_string(), Json::List(tiles))])
+    }
+
+    fn from_json(json: &Json) -> Option<Block> {
+        let mut blk = Block::new_at(json.get("x")?.num()? as i16,
+                                    json.get("y")?.num()? as i16);
+        for tile in json.get("tiles")?.list()? {
+            match tile.list()? {
+                [dx, dy, code] => blk.tiles.push((dx.num()? as i16, dy.num()? as i16,
+                                                  TileType::from_code(code.str()?)?)),
+                _ => return None,
+            }
+        }
+        Some(blk)
+    }
+
+    // The w x h fields from the origin in the format that from_map
+    // takes.
+    pub fn to_map(&self, w: i16, h: i16) -> String {
+        let mut map = String::new();
+        for y in 0..h {
+            for x in 0..w {
+                map.push(self.at(x, y).map_or('.', |tt| tt.glyph()));
+            }
+            map.push('\n');
+        }
+        map
+    }
+
+    pub fn new_from_shape<F: FnMut() -> TileType>(shape: &[(i16, i16)], mut tile: F) -> Block {
+        let mut rtiles = Vec::new();
+        for &(dx, dy) in shape {
+            rtiles.push((dx, dy, tile()));
+        }
+        Block {x:0, y:0, tiles:rtiles}
+    }
+
+    pub fn new_random<R: Rng>(lvl: u8, rng: &mut R) -> Block {
+        let shape = Block::random_shape(rng);
+        Block::new_from_shape(shape, || TileType::new_random(lvl, rng))
+    }
+
+    // Like new_random, but of the n-th of the SHAPES shapes.
+    pub fn new_random_of<R: Rng>(n: usize, lvl: u8, rng: &mut R) -> Block {
+        Block::new_from_shape(Block::shape(n), || TileType::new_random(lvl, rng))
+    }
+
+    // Plain tiles strewn over the given number of rows at the bottom
+    // of a pgw x pgh playground, on about every other free field of
+    // pg.  Tiles that would have anything explode are left out, and
+    // so are the border and the rows where new blocks appear.
+    pub fn random_fill<R: Rng>(rows: usize, pg: &Block, pgw: i16, pgh: i16,
+                           rng: &mut R) -> Block {
+        let mut board = pg.clone();
+        let mut fill = Block::new();
+        let top = cmp::max(pgh - 1 - rows as i16, SPAWN_SIZE + 1);
+        for y in top..pgh - 1 {
+            for x in 1..pgw - 1 {
+                if board.at(x, y).is_some() || !rng.gen::<bool>() {
+                    continue;
+                }
+                board.tiles.push((x - board.x, y - board.y, TileType::Plain(0)));
+                if board.matches().is_empty() {
+                    fill.tiles.push((x, y, TileType::Plain(0)));
+                } else {
+                    board.tiles.pop();
+                }
+            }
+        }
+        fill
+    }
+
+    // A block of plain tiles, the easiest kind to get rid of.
+    pub fn new_plain<R: Rng>(rng: &mut R) -> Block {
+        let shape = Block::random_shape(rng);
+        Block::new_from_shape(shape, || TileType::Plain(0))
+    }
+
+    pub fn random_shape<R: Rng>(rng: &mut R) -> &'static [(i16, i16)] {
+        Block::shape(rng.gen_range(0, SHAPES))
+    }
+
+    pub fn shape(n: usize) -> &'static [(i16, i16)] {
+        fn shape_1x1() -> &'static [(i16, i16)] {
+            static SHAPE:[(i16, i16); 1] = [(0, 0)];
+            &SHAPE
+        }
+
+        fn shape_8() -> &'static [(i16, i16)] {
+            static SHAPE:[(i16, i16); 2] = [( 0, -1), ( 0,  1)];
+            &SHAPE
+        }
+
+        fn shape_d() -> &'static [(i16, i16)] {
+            static SHAPE:[(i16, i16); 2] = [(-1, -1), ( 0, 0)];
+            &SHAPE
+        }
+
+        fn shape_l() -> &'static [(i16, i16)] {
+            static SHAPE:[(i16, i16); 3] = [(-1, 0), (0,  0), ( 0, -1)];
+            &SHAPE
+        }
+
+        fn shape_1x2() -> &'static [(i16, i16)] {
+            static SHAPE:[(i16, i16); 2]
+                = [( 0, -1), ( 0,  0)];
+            &SHAPE
+        }
+
+        fn shape_1x3() -> &'static [(i16, i16)] {
+            static SHAPE:[(i16, i16); 3]
+                = [( 0, -1), ( 0,  0), (0,  1)];
+            &SHAPE
+        }
+
+        fn shape_castle() -> &'static [(i16, i16)] {
+            static SHAPE:[(i16, i16); 3] = [( 0, -1), (-1,  0), ( 1,  0)];
+            &SHAPE
+        }
+
+        match n {
+            0 => shape_1x1(),
+            1 => shape_1x2(),
+            2 => shape_1x3(),
+            3 => shape_8(),
+            4 => shape_d(),
+            5 => shape_l(),
+            6 => shape_castle(),
+            _ => unreachable!(),
+        }
+    }
+
+    // The sides are laid out like a pinwheel, each taking one corner
+    // and leaving the other to the next side, so that every perimeter
+    // field is covered exactly once.  That only works out with at
+    // least two fields per side, anything thinner isn't a closed
+    // rectangle anyway.
+    pub fn new_border(w: i16, h: i16) -> Block {
+        assert!(w >= 2 && h >= 2, "a border needs to be at least 2x2, not {}x{}", w, h);
+
+        let mut tiles = Vec::new();
+        for x in 0..w-1 {
+            tiles.push((x, 0, TileType::Permanent));
+            tiles.push((x+1, h-1, TileType::Permanent));
+        }
+        for y in 0..h-1 {
+            tiles.push((0, y+1, TileType::Permanent));
+            tiles.push((w-1, y, TileType::Permanent));
+        }
+        Block {x:0, y:0, tiles}
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn paint_tile(x0: i16, y0: i16, w: i16, h: i16, grid: &mut Grid,
+                  have_up: bool, have_right: bool,
+                  have_down: bool, have_left: bool,
+                  pen1: Pen, pen2: Pen) {
+        let x1 = x0 + w;
+        let y1 = y0 + h;
+        let pen = |b: bool| { if b { pen1 } else { pen2 } };
+
+        for &(x, y, len, d, n) in [(x0, y0, w, Direction::Right, have_up),
+                                   (x1, y0, h, Direction::Down, have_right),
+                                   (x0, y1, w, Direction::Right, have_down),
+                                   (x0, y0, h, Direction::Down, have_left)].iter() {
+            grid.paint_wall(x, y, len, d, !n, pen(n));
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn paint1(&self, x: i16, y: i16, tt: TileType, grid: &mut Grid,
+              pen1: Pen, pen2: Pen, look: Look) {
+        let face = if look.ascii {
+            tt.render_ascii()
+        } else if look.distinct {
+            tt.render_distinct()
+        } else {
+            tt.render().to_string()
+        };
+
+        let up = self.at(x, y-1);
+        let right = self.at(x+1, y);
+        let down = self.at(x, y+1);
+        let left = self.at(x-1, y);
+
+        // A tile is 5x3, but the walls are shared, so we place
+        // them to dx*4, dy*2.
+        let tx = 4 * x;
+        let ty = 2 * y;
+        grid.clear(tx, ty, 5, 3);
+
+        if tt.is_solid() {
+            fn is_solid_neighbor(n: Option<TileType>) -> bool {
+                if let Some(tt) = n {
+                    tt.is_solid()
+                } else {
+                    false
+                }
+            }
+
+            Block::paint_tile(tx, ty, 4, 2, grid,
+                              is_solid_neighbor(up), is_solid_neighbor(right),
+                              is_solid_neighbor(down), is_solid_neighbor(left),
+                              pen1, pen2);
+            grid.paint_decoration(tx + 1, ty + 1, &face);
+            grid.color_rect(tx + 1, ty + 1, 3, 1, tt.color());
+        } else {
+            let c = face;
+            grid.paint_decoration(tx, ty, &format!(" {} {} ", c, c));
+            grid.paint_decoration(tx, ty+1, &format!("{} {} {}", c, c, c));
+            grid.paint_decoration(tx, ty+2, &format!(" {} {} ", c, c));
+            grid.color_rect(tx, ty, 5, 3, tt.color());
+        }
+    }
+
+    // The outer walls of the block are drawn with the outline pen of
+    // the look, the inner ones are always thin.
+    pub fn paint(&self, grid: &mut Grid, look: Look) {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+
+        for &(dx, dy, tt) in tiles {
+            if ! tt.is_solid() {
+                self.paint1(x0 + dx, y0 + dy, tt, grid,
+                            Pen::Thin, look.outline, look);
+            }
+        }
+
+        for &(dx, dy, tt) in tiles {
+            if tt.is_solid() {
+                self.paint1(x0 + dx, y0 + dy, tt, grid,
+                            Pen::Thin, look.outline, look);
+            }
+        }
+    }
+
+    // Paint the solid tiles in dashed lines over whatever is in the
+    // grid already.
+    pub fn paint_ghost(&self, grid: &mut Grid, look: Look) {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+
+        for &(dx, dy, tt) in tiles {
+            if tt.is_solid() {
+                self.paint1(x0 + dx, y0 + dy, tt, grid,
+                            Pen::Dash, Pen::Dash, look);
+            }
+        }
+    }
+
+    // Blocks land right where they are, so what the player needs to
+    // know is what the landing would set off.  Returns the tiles that
+    // dropping the block now would explode, or an empty block if it
+    // can't be dropped here.
+    pub fn ghost(&self, pg: &Block, bd: &Block) -> Block {
+        let tiles = match simulate_drop(pg, self, bd) {
+            Some(outcome) => outcome.exploded,
+            None => Vec::new(),
+        };
+        Block {x:0, y:0, tiles}
+    }
+
+    // The block moved down as far as it goes, as a hard drop does.
+    pub fn hard_dropped(&self, pg: &Block, bd: &Block) -> Block {
+        let mut blk = self.clone();
+        while !block_collides(&blk.moved(0, 1), bd, pg) {
+            blk = blk.moved(0, 1);
+        }
+        blk
+    }
+
+    // Dashed lines from the bottom of each column of the block down to
+    // where a hard drop would take it.  There's nothing to draw for a
+    // block that can't go further down.
+    pub fn paint_guide(&self, grid: &mut Grid, pg: &Block, bd: &Block) {
+        let rows = self.hard_dropped(pg, bd).y - self.y;
+        if rows < 2 {
+            return;
+        }
+        let mut bottoms: Vec<(i16, i16)> = Vec::new();
+        for &(dx, dy, _) in &self.tiles {
+            match bottoms.iter().position(|&(x, _)| x == dx) {
+                Some(i) => bottoms[i].1 = cmp::max(bottoms[i].1, dy),
+                None => bottoms.push((dx, dy)),
+            }
+        }
+        for (dx, dy) in bottoms {
+            let (gx, gy) = (4 * (self.x + dx) + 2, 2 * (self.y + dy + 1));
+            grid.paint_wall(gx, gy, 2 * (rows - 1), Direction::Down, false, Pen::Dash);
+            grid.color_drawings(gx, gy, 1, 2 * (rows - 1), PAIR_DOTS);
+        }
+    }
+
+    pub fn at(&self, x: i16, y: i16) -> Option<TileType> {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+        for &(dx, dy, tt) in tiles {
+            if x == x0+dx && y == y0+dy {
+                return Some(tt)
+            }
+        }
+        None
+    }
+
+    pub fn turned(&self) -> Block {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+
+        let mut rtiles = Vec::with_capacity(tiles.len());
+        for &(dx, dy, tt) in tiles {
+            rtiles.push((dy, -dx, tt));
+        }
+        Block {x:x0, y:y0, tiles:rtiles}
+    }
+
+    // The other way round than turned.
+    pub fn turned_ccw(&self) -> Block {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+
+        let mut rtiles = Vec::with_capacity(tiles.len());
+        for &(dx, dy, tt) in tiles {
+            rtiles.push((-dy, dx, tt));
+        }
+        Block {x:x0, y:y0, tiles:rtiles}
+    }
+
+    // Mirrored left to right.
+    pub fn flipped_h(&self) -> Block {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+
+        let mut rtiles = Vec::with_capacity(tiles.len());
+        for &(dx, dy, tt) in tiles {
+            rtiles.push((-dx, dy, tt));
+        }
+        Block {x:x0, y:y0, tiles:rtiles}
+    }
+
+    // Mirrored top to bottom.
+    pub fn flipped_v(&self) -> Block {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+
+        let mut rtiles = Vec::with_capacity(tiles.len());
+        for &(dx, dy, tt) in tiles {
+            rtiles.push((dx, -dy, tt));
+        }
+        Block {x:x0, y:y0, tiles:rtiles}
+    }
+
+    pub fn moved(&self, dx: i16, dy: i16) -> Block {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+        let mut rtiles = Vec::with_capacity(tiles.len());
+        for &tile in tiles {
+            rtiles.push(tile);
+        }
+        Block {x:x0+dx, y:y0+dy, tiles:rtiles}
+    }
+
+    pub fn moved_to(&self, x: i16, y: i16) -> Block {
+        self.moved(x - self.x, y - self.y)
+    }
+
+    // The smallest and the largest x and y that the tiles are at, or
+    // None if there are no tiles.
+    pub fn bounds(&self) -> Option<(i16, i16, i16, i16)> {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+        let xs = || tiles.iter().map(|&(dx, _, _)| x0 + dx);
+        let ys = || tiles.iter().map(|&(_, dy, _)| y0 + dy);
+        match (xs().min(), ys().min(), xs().max(), ys().max()) {
+            (Some(x1), Some(y1), Some(x2), Some(y2)) => Some((x1, y1, x2, y2)),
+            _ => None,
+        }
+    }
+
+    // Collide blk1 into blk2 and return both blocks as they end up.
+    // The third element lists places where a tile of blk1 ran into
+    // a spill: acid dissolves the tile, glue sticks it to blk2.
+    pub fn collide(blk1: Block, blk2: &Block)
+               -> (Block, Block, Vec<(i16, i16, LiquidType)>) {
+        let Block {x:x1, y:y1, tiles:tiles1} = blk1;
+        let mut rtiles1 = Vec::new();
+        let mut splashes = Vec::new();
+
+        let &Block {x:x2, y:y2, ..} = blk2;
+        let mut rtiles2 = Vec::new();
+        for &(dx, dy, tt2) in &blk2.tiles {
+            rtiles2.push((dx, dy, tt2));
+        }
+
+        for (dx1, dy1, tt1) in tiles1 {
+            let xx1 = x1 + dx1;
+            let yy1 = y1 + dy1;
+            if let Some(tt2) = blk2.at(xx1, yy1) {
+                if TileType::collides(tt1, tt2) {
+                    if let TileType::Spillage(liquid) = tt2 {
+                        splashes.push((xx1, yy1, liquid));
+                    }
+
+                    let (nt1, nt2) = TileType::collide(tt1, tt2);
+                    if let Some(ntt1) = nt1 {
+                        rtiles1.push((dx1, dy1, ntt1));
+                    }
+                    rtiles2.retain(|&(dx2, dy2, _): &(i16, i16, TileType)|
+                                   x2 + dx2 != xx1 || y2 + dy2 != yy1);
+                    if let Some(ntt2) = nt2 {
+                        rtiles2.push((xx1 - blk2.x, yy1 - blk2.y, ntt2));
+                    }
+                } else {
+                    rtiles1.push((dx1, dy1, tt1));
+                }
+            } else {
+                rtiles1.push((dx1, dy1, tt1));
+            }
+        }
+
+        (Block {x:blk1.x, y:blk1.y, tiles:rtiles1},
+         Block {x:blk2.x, y:blk2.y, tiles:rtiles2},
+         splashes)
+    }
+
+    pub fn intersects(&self, blk2: &Block) -> bool {
+        let &Block {x:x1, y:y1, ref tiles} = self;
+        for &(dx1, dy1, _) in tiles {
+            let xx1 = x1 + dx1;
+            let yy1 = y1 + dy1;
+            if blk2.at(xx1, yy1).is_some() {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn drop(&self, dest: &mut Block, bd: &Block) -> bool {
+        if self.intersects(bd) || self.intersects(dest) {
+            return false;
+        }
+
+        let &Block {x:x1, y:y1, ref tiles} = self;
+        let &mut Block {x:x2, y:y2, tiles:ref mut dtiles} = dest;
+        let ddx = x1 - x2;
+        let ddy = y1 - y2;
+        for &(dx1, dy1, tt1) in tiles {
+            if let Some(ntt) = tt1.drop() {
+                dtiles.push((dx1 + ddx, dy1 + ddy, ntt));
+            }
+        }
+
+        true
+    }
+
+    pub fn collides_with(&self, with: &Block) -> bool {
+        let &Block {x:x0, y:y0, ref tiles} = self;
+        for &(dx, dy, tt1) in tiles {
+            if let Some(tt2) = with.at(x0+dx, y0+dy) {
+                if TileType::collides(tt1, tt2) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn spill(x: i16, y: i16, spills: &mut Vec<(i16, i16, LiquidType)>, liquid: LiquidType) {
+        for &(dx, dy) in liquid.spill_shape() {
+            spills.push((x+dx, y+dy, liquid));
+        }
+    }
+
+    // Find fields that explode() would explode, without exploding
+    // them.  The coordinates are absolute and may repeat.
+    pub fn matches(&self) -> Vec<(i16, i16)> {
+        let mut killlist = Vec::new();
+
+        'next: for &(xx, yy, tt) in &self.tiles {
+            let mut sublist = Vec::new();
+            for &(dx, dy) in tt.explode_shape() {
+                let x2 = self.x + xx + dx;
+                let y2 = self.y + yy + dy;
+                match self.at(x2, y2) {
+                    None => continue 'next,
+                    Some(tt2) => if tt.explodes(tt2) {
+                        sublist.push((x2, y2));
+                    } else {
+                        continue 'next
+                    },
+                }
+            }
+            for i in sublist {
+                killlist.push(i);
+            }
+        }
+
+        killlist
+    }
+
+    // Explode whatever 3x3 (or 5x5) areas there are, and tell what
+    // that did.
+    //
+    // Tiles are processed in row-major order, by y and then x, which
+    // is also the order in which the exploded tiles are returned.
+    // The outcome therefore doesn't depend on the order in which the
+    // tiles were added to the block.
+    //
+    // Exploded bombs additionally sweep away every tile left in their
+    // row and column.  Those are scored as exploded as well, but
+    // their own explode actions don't apply.
+    //
+    // Exploded chaos tiles shuffle the solid tiles that are left among
+    // their places, except for permanent ones, which stay put.  The
+    // shuffle is seeded by the board itself, so that the same board
+    // always shuffles the same way, and simulate_drop can tell what
+    // a drop will do.
+    //
+    // Spills only go where there's nothing, or a spill of the other
+    // liquid, which they react with.  Acid that lands on glue
+    // neutralizes it, and both are gone.  Glue that lands on acid sets
+    // into a plain tile.  Spills land in the order of the exploded
+    // flasks, so the same goes for two flasks spilling over one field.
+    //
+    // Exploded converters turn the tiles left above, below and to the
+    // sides of them into plain ones, save for permanent tiles and
+    // spills.  That happens after the bombs, so whatever a bomb swept
+    // away stays gone.
+    pub fn explode(&mut self) -> Explosion {
+        self.tiles.sort_by_key(|&(x, y, _)| (y, x));
+        let killlist = self.matches();
+
+        // What the explode actions leave for after all tiles are
+        // through.
+        #[derive(Default)]
+        struct Effects {
+            spills: Vec<(i16, i16, LiquidType)>,
+            clears: Vec<(i16, i16)>,
+            converts: Vec<(i16, i16)>,
+            shuffle: bool,
+        }
+
+        fn handle_xp_action(xa: ExplodeAction, xx: i16, yy: i16, xp: &mut Explosion,
+                            fx: &mut Effects, rtiles: &mut Vec<(i16, i16, TileType)>) {
+            match xa {
+                ExplodeAction::Remove => {},
+                ExplodeAction::Convert(tt2) => rtiles.push((xx, yy, tt2)),
+                ExplodeAction::Spill(liquid) => Block::spill(xx, yy, &mut fx.spills, liquid),
+                ExplodeAction::Plus => xp.dmult += 1,
+                ExplodeAction::Minus => xp.dmult -= 1,
+                ExplodeAction::Clear => fx.clears.push((xx, yy)),
+                ExplodeAction::AddTime(ms) => xp.time += ms,
+                ExplodeAction::Shuffle => fx.shuffle = true,
+                ExplodeAction::ConvertNeighbors => fx.converts.push((xx, yy)),
+                ExplodeAction::SlowDown => xp.slowdown = true,
+                ExplodeAction::Meteor => xp.meteors += 1,
+                ExplodeAction::Complex(a, b) => {
+                    handle_xp_action(*a, xx, yy, xp, fx, rtiles);
+                    handle_xp_action(*b, xx, yy, xp, fx, rtiles);
+                },
+            }
+        }
+
+        let mut xp = Explosion {exploded: Vec::new(), hits: 0, dmult: 0, time: 0,
+                                slowdown: false, meteors: 0};
+        let mut fx = Effects::default();
+        {
+            let mut rtiles = Vec::new();
+            'next2: for &(xx, yy, tt) in &self.tiles {
+                for &(x2, y2) in &killlist {
+                    if self.x + xx == x2 && self.y + yy == y2 {
+                        xp.exploded.push((xx, yy, tt));
+                        handle_xp_action(tt.explode(), xx, yy, &mut xp, &mut fx, &mut rtiles);
+                        xp.hits += tt.bonus();
+                        continue 'next2;
+                    }
+                }
+                rtiles.push((xx, yy, tt));
+            }
+
+            if !fx.clears.is_empty() {
+                let clears = &fx.clears;
+                let (swept, rest): (Vec<_>, Vec<_>) = rtiles.into_iter().partition(
+                    |&(xx, yy, tt)| tt.is_solid()
+                        && clears.iter().any(|&(cx, cy)| xx == cx || yy == cy));
+                for (xx, yy, tt) in swept {
+                    xp.exploded.push((xx, yy, tt));
+                    xp.hits += tt.bonus();
+                }
+                xp.exploded.sort_by_key(|&(x, y, _)| (y, x));
+                rtiles = rest;
+            }
+
+            for &mut (xx, yy, ref mut tt) in &mut rtiles {
+                if tt.is_solid() && *tt != TileType::Permanent
+                    && fx.converts.iter().any(|&(cx, cy)| (xx - cx).abs() + (yy - cy).abs() == 1) {
+                    *tt = TileType::Plain(0);
+                }
+            }
+
+            self.tiles = rtiles;
+
+            for &(xx, yy, liquid) in &fx.spills {
+                match self.at(xx, yy) {
+                    None => self.tiles.push((xx, yy, TileType::Spillage(liquid))),
+                    Some(TileType::Spillage(other)) if other != liquid => {
+                        self.tiles.retain(|&(x, y, _)| (x, y) != (xx, yy));
+                        if liquid == LiquidType::Glue {
+                            self.tiles.push((xx, yy, TileType::Plain(0)));
+                        }
+                    },
+                    Some(_) => {},
+                }
+            }
+        }
+
+        if fx.shuffle {
+            let seed = self.tiles.iter().fold(0usize, |h, &(x, y, _)| {
+                h.wrapping_mul(31).wrapping_add(((x as u16 as usize) << 16) | y as u16 as usize)
+            });
+            let mut rng: StdRng = SeedableRng::from_seed(&[seed][..]);
+
+            let places: Vec<usize> = (0..self.tiles.len())
+                .filter(|&i| self.tiles[i].2.is_solid() && self.tiles[i].2 != TileType::Permanent)
+                .collect();
+            let mut types: Vec<TileType> = places.iter().map(|&i| self.tiles[i].2).collect();
+            rng.shuffle(&mut types);
+            for (&i, tt) in places.iter().zip(types) {
+                self.tiles[i].2 = tt;
+            }
+        }
+
+        if xp.exploded.len() > BIG_EXPLOSION {
+            xp.dmult += (xp.exploded.len() as i32 - 9) / 9;
+        }
+
+        xp
+    }
+}
+
+// Explosions of more than this many tiles are big.  They bring a
+// multiplier bonus, and their score comes in a burst.
+pub const BIG_EXPLOSION: usize = 12;
+
+// How to draw things.  Some of it is a setting, the rest comes from the
+// command line.
+#[derive(Copy, Clone, Debug)]
+pub struct Look {
+    // The pen that tiles are outlined with.
+    pub outline: Pen,
+
+    // Stick to ASCII characters when drawing tiles.
+    pub ascii: bool,
+
+    // Leave out the dots that only decorate the background.
+    pub plain: bool,
+
+    // Draw tiles with render_distinct.  ASCII goes first.
+    pub distinct: bool,
+}
+
+// Just enough JSON for board snapshots, see Block::to_snapshot.
+// Numbers are integers, and strings only know the \" and \\ escapes.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Num(i64),
+    Str(String),
+    List(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut chars = text.chars().peekable();
+        let json = Json::parse_value(&mut chars)?;
+        Json::skip_space(&mut chars);
+        match chars.next() {
+            None => Ok(json),
+            Some(c) => Err(format!("unexpected '{}' after the end", c)),
+        }
+    }
+
+    fn skip_space(chars: &mut iter::Peekable<str::Chars>) {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+
+    fn parse_value(chars: &mut iter::Peekable<str::Chars>) -> Result<Json, String> {
+        // Lists and objects are the same but for the keys.
+        fn items(chars: &mut iter::Peekable<str::Chars>, end: char,
+                 keyed: bool) -> Result<Vec<(String, Json)>, String> {
+            let mut items = Vec::new();
+            Json::skip_space(chars);
+            if chars.peek() == Some(&end) {
+                chars.next();
+                return Ok(items);
+            }
+            loop {
+                let key = if keyed {
+                    let key = match Json::parse_value(chars)? {
+                        Json::Str(key) => key,
+                        _ => return Err("expected a key".to_string()),
+                    };
+                    Json::skip_space(chars);
+                    if chars.next() != Some(':') {
+                        return Err("expected ':'".to_string());
+                    }
+                    key
+                } else {
+                    String::new()
+                };
+                items.push((key, Json::parse_value(chars)?));
+
+                Json::skip_space(chars);
+                match chars.next() {
+                    Some(',') => {},
+                    Some(c) if c == end => return Ok(items),
+                    _ => return Err(format!("expected ',' or '{}'", end)),
+                }
+            }
+        }
+
+        Json::skip_space(chars);
+        match chars.next() {
+            Some('[') => Ok(Json::List(items(chars, ']', false)?
+                                       .into_iter().map(|(_, v)| v).collect())),
+            Some('{') => Ok(Json::Obj(items(chars, '}', true)?)),
+            Some('"') => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => return Ok(Json::Str(s)),
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => s.push(c),
+                            _ => return Err("unknown escape".to_string()),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+            },
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let mut num = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    num.push(c);
+                    chars.next();
+                }
+                num.parse().map(Json::Num).map_err(|_| format!("bad number {}", num))
+            },
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Obj(ref items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn num(&self) -> Option<i64> {
+        match *self {
+            Json::Num(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn str(&self) -> Option<&str> {
+        match *self {
+            Json::Str(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn list(&self) -> Option<&[Json]> {
+        match *self {
+            Json::List(ref items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Num(n) => write!(f, "{}", n),
+            Json::Str(ref s) => write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+            Json::List(ref items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { "," } else { "" }, item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Obj(ref items) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in items.iter().enumerate() {
+                    write!(f, "{}{}:{}", if i > 0 { "," } else { "" }, Json::Str(k.clone()), v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+// New blocks appear in the top left corner, within this many fields
+// of the border.
+pub const SPAWN_SIZE: i16 = 3;
+
+pub fn block_collides(block: &Block, bd: &Block, pg: &Block) -> bool {
+    block.collides_with(bd) || block.collides_with(pg)
+}
+
+// What dropping a block onto the playground leads to.
+pub struct DropOutcome {
+    pub pg: Block,
+    pub exploded: Vec<(i16, i16, TileType)>,
+    pub hits: u32,
+    pub dmult: i32,
+
+    // Milliseconds that exploded clocks put back on the drop gauge.
+    pub time: i64,
+
+    // Whether an exploded hourglass slows the gauges down.
+    pub slowdown: bool,
+
+    // How many exploded meteors rain tiles down.
+    pub meteors: u32,
+}
+
+// Drop blk onto a copy of pg and explode the result.  In the game, a
+// block collides with the tiles it's moved onto on its way, see
+// Block::collide, so that it never sits on any by the time it's
+// dropped.  Here blk is taken as moved to where it is, and collides
+// with whatever it sits on before it's dropped.  After that, it's the
+// same drop and explosion as in the game.  Returns None if blk can't
+// be dropped where it is.
+pub fn simulate_drop(pg: &Block, blk: &Block, bd: &Block) -> Option<DropOutcome> {
+    let (blk, mut pg, _) = Block::collide(blk.clone(), pg);
+    if !blk.drop(&mut pg, bd) {
+        return None;
+    }
+
+    let xp = pg.explode();
+    Some(DropOutcome {pg, exploded: xp.exploded, hits: xp.hits, dmult: xp.dmult,
+                      time: xp.time, slowdown: xp.slowdown, meteors: xp.meteors})
+}
+
+// The board with the block to place on it, painted the way the game
+// paints it, minus the background and anything else that only the
+// terminal frontend shows.  Its to_string is the board as text, which
+// is all a frontend without ncurses, like a terminal in a browser,
+// needs to show a game.
+pub fn paint_board(pg: &Block, bd: &Block, blk: &Block, look: Look) -> Grid {
+    let (w, h) = match bd.bounds() {
+        Some((_, _, x2, y2)) => (x2 + 1, y2 + 1),
+        None => (0, 0),
+    };
+    let mut grid = Grid::new(4 * w, 2 * h);
+    pg.paint(&mut grid, look);
+    blk.ghost(pg, bd).paint_ghost(&mut grid, look);
+    bd.paint(&mut grid, look);
+    blk.paint(&mut grid, look);
+    grid
+}
+
+// Color pairs of things other than tiles.  Pairs 1 to 7 are the tile
+// families that TileType::color hands out.
+pub const PAIR_BORDER: i16 = 8;
+pub const PAIR_DOTS: i16 = 9;
+pub const PAIR_HUD: i16 = 10;
+pub const PAIR_GAUGE_PLENTY: i16 = 11;
+pub const PAIR_GAUGE_HALF: i16 = 12;
+pub const PAIR_GAUGE_LOW: i16 = 13;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(map: &str) -> Block {
+        Block::from_map(map).unwrap()
+    }
+
+    fn row(grid: &Grid, y: i16) -> String {
+        (0..grid.w + 1).map(|x| grid.field_char(x, y, false).unwrap_or(' ')).collect()
+    }
+
+    #[test]
+    fn draw_rect_corners() {
+        let mut grid = Grid::new(4, 2);
+        grid.draw_rect(0, 0, 5, 3, Pen::Thin);
+        assert_eq!(row(&grid, 0), "┌───┐");
+        assert_eq!(row(&grid, 1), "│   │");
+        assert_eq!(row(&grid, 2), "└───┘");
+    }
+
+    #[test]
+    fn draw_rect_junctions() {
+        // Four rectangles that share their sides, like the lines in
+        // the corner where new blocks appear.
+        let mut grid = Grid::new(8, 4);
+        for &(x, y) in &[(0, 0), (4, 0), (0, 2), (4, 2)] {
+            grid.draw_rect(x, y, 5, 3, Pen::Thin);
+        }
+        assert_eq!(row(&grid, 0), "┌───┬───┐");
+        assert_eq!(row(&grid, 2), "├───┼───┤");
+        assert_eq!(row(&grid, 4), "└───┴───┘");
+    }
+
+    #[test]
+    fn plain_square_explodes() {
+        let mut pg = board("***\n\
+                            ***\n\
+                            ***\n");
+        let xp = pg.explode();
+        assert_eq!(xp.exploded.len(), 9);
+        assert_eq!(xp.hits, 9);
+        assert!(pg.tiles.is_empty());
+    }
+
+    #[test]
+    fn every_drawing_renders() {
+        let pens = [Pen::None, Pen::Thin, Pen::Thik, Pen::Dash, Pen::Round];
+        for &up in &pens {
+            for &right in &pens {
+                for &down in &pens {
+                    for &left in &pens {
+                        let dw = FieldDrawing {up, right, down, left,
+                                               rise: Pen::None, fall: Pen::None};
+                        let s = Grid::render_field_drawing(dw);
+                        let blank = [up, right, down, left].iter().all(|&p| matches!(p, Pen::None));
+                        assert_eq!(s == " ", blank, "{:?} is {:?}", dw, s);
+
+                        // Box drawing characters are all one column
+                        // wide.
+                        let cs: Vec<char> = s.chars().collect();
+                        assert_eq!(cs.len(), 1, "{:?} is {:?}", dw, s);
+                        assert!(blank || ('\u{2500}'..='\u{257f}').contains(&cs[0]),
+                                "{:?} is {:?}", dw, s);
+
+                        let ascii = Grid::render_field_drawing_ascii(dw);
+                        assert_eq!(ascii.len(), 1, "{:?} is {:?}", dw, ascii);
+                        assert_eq!(ascii == " ", blank, "{:?} is {:?}", dw, ascii);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ascii_drawings() {
+        let n = Pen::None;
+        for &p in &[Pen::Thin, Pen::Thik] {
+            let cases = [((n, p, p, n), "+"), ((n, n, p, p), "+"),
+                         ((p, p, n, n), "+"), ((p, n, n, p), "+"),
+                         ((p, p, p, n), "+"), ((p, n, p, p), "+"),
+                         ((n, p, p, p), "+"), ((p, p, n, p), "+"),
+                         ((p, p, p, p), "+"),
+                         ((n, p, n, p), "-"), ((n, p, n, n), "-"),
+                         ((p, n, p, n), "|"), ((p, n, n, n), "|")];
+            for &((up, right, down, left), ascii) in &cases {
+                let dw = FieldDrawing {up, right, down, left, rise: n, fall: n};
+                assert_eq!(Grid::render_field_drawing_ascii(dw), ascii, "{:?}", dw);
+            }
+        }
+    }
+
+    #[test]
+    fn explode_ignores_tile_order() {
+        let pg = board("*1*2\n\
+                        **a*\n\
+                        1***\n\
+                        ****\n");
+        let mut reversed = pg.clone();
+        reversed.tiles.reverse();
+        let mut forward = pg.clone();
+
+        let outcome = forward.explode();
+        assert!(!outcome.exploded.is_empty());
+        assert_eq!(outcome, reversed.explode());
+        assert_eq!(forward.tiles, reversed.tiles);
+    }
+
+    // Always draws the last tile slot, which isn't available early on.
+    struct Stuck;
+
+    impl Rng for Stuck {
+        fn next_u32(&mut self) -> u32 {
+            38
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            38
+        }
+    }
+
+    #[test]
+    fn new_random_gives_up() {
+        assert_eq!(TileType::new_random(0, &mut Stuck), TileType::Plain(0));
+    }
+
+    #[test]
+    fn border_is_closed() {
+        for &(w, h) in &[(2, 2), (2, 5), (5, 2), (3, 3), (16, 12), (7, 30)] {
+            let bd = Block::new_border(w, h);
+            for y in 0..h {
+                for x in 0..w {
+                    let edge = x == 0 || y == 0 || x == w - 1 || y == h - 1;
+                    let count = bd.tiles.iter()
+                        .filter(|&&(dx, dy, _)| (bd.x + dx, bd.y + dy) == (x, y)).count();
+                    assert_eq!(count, if edge { 1 } else { 0 },
+                               "{}x{} border at {}, {}", w, h, x, y);
+                }
+            }
+            assert_eq!(bd.tiles.len() as i16, 2 * (w + h) - 4);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn thin_border() {
+        Block::new_border(1, 5);
+    }
+
+    #[test]
+    fn zero_size_grid() {
+        let mut grid = Grid::new(0, 0);
+        assert_eq!(grid.to_string(), " \n");
+        grid.paint_decoration(0, 0, "xy");
+        assert_eq!(grid.to_string(), "x\n");
+        grid.paint(0, 0, Direction::Right, Pen::Thin);
+        assert_eq!(grid.to_string(), "╶\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_grid() {
+        Grid::new(3, -1);
+    }
+
+    // An L of three different tiles, so that any turn or flip shows.
+    fn ell() -> Block {
+        let mut blk = Block::new_at(4, 4);
+        blk.tiles = vec![(-1, 0, TileType::Killer(1)), (0, 0, TileType::Plain(0)),
+                         (0, -1, TileType::Picker)];
+        blk
+    }
+
+    #[test]
+    fn turns() {
+        let blk = ell();
+        let turned = blk.turned();
+        assert!(turned.tiles != blk.tiles);
+        assert_eq!(turned.turned().turned().turned().tiles, blk.tiles);
+        assert_eq!(turned.turned_ccw().tiles, blk.tiles);
+        assert_eq!(blk.turned_ccw().turned().tiles, blk.tiles);
+        assert_eq!(blk.turned_ccw().tiles, turned.turned().turned().tiles);
+        assert_eq!((turned.x, turned.y), (blk.x, blk.y));
+    }
+
+    #[test]
+    fn flips() {
+        let blk = ell();
+        assert_eq!(blk.flipped_h().tiles,
+                   vec![(1, 0, TileType::Killer(1)), (0, 0, TileType::Plain(0)),
+                        (0, -1, TileType::Picker)]);
+        assert_eq!(blk.flipped_v().tiles,
+                   vec![(-1, 0, TileType::Killer(1)), (0, 0, TileType::Plain(0)),
+                        (0, 1, TileType::Picker)]);
+        assert_eq!(blk.flipped_h().flipped_h().tiles, blk.tiles);
+        assert_eq!(blk.flipped_v().flipped_v().tiles, blk.tiles);
+
+        // Flipping both ways is half a turn, and a flipped block
+        // turns the other way round.
+        assert_eq!(blk.flipped_h().flipped_v().tiles, blk.turned().turned().tiles);
+        assert_eq!(blk.turned().flipped_h().tiles, blk.flipped_h().turned_ccw().tiles);
+        assert_eq!((blk.flipped_h().x, blk.flipped_v().y), (blk.x, blk.y));
+    }
+
+    #[test]
+    fn bomb_sweeps_row_and_column() {
+        let mut pg = board("..#.....\n\
+                            ..*.....\n\
+                            .***....\n\
+                            .*B*..*~\n\
+                            .***....\n\
+                            ......X.\n");
+        let exploded = pg.explode().exploded;
+
+        assert_eq!(exploded.len(), 12);
+        for &(x, y) in &[(2, 0), (2, 1), (6, 3)] {
+            assert!(exploded.iter().any(|&(xx, yy, _)| (xx, yy) == (x, y)));
+        }
+        assert_eq!(pg.tiles, vec![(7, 3, TileType::Spillage(LiquidType::Glue)),
+                                  (6, 5, TileType::Killer(1))]);
+    }
+
+    #[test]
+    fn killers_collide() {
+        use TileType::*;
+        assert_eq!(TileType::collide(Killer(1), Killer(1)), (None, None));
+        assert_eq!(TileType::collide(Killer(3), Killer(1)), (Some(Killer(2)), None));
+        assert_eq!(TileType::collide(Killer(1), Killer(3)), (None, Some(Killer(2))));
+        assert_eq!(TileType::collide(Killer(2), Killer(4)),
+                   (Some(Killer(1)), Some(Killer(3))));
+
+        // Pickers get the upper hand over killers, either way round.
+        assert_eq!(TileType::collide(Killer(2), Picker), (None, Some(Plain(0))));
+        assert_eq!(TileType::collide(Picker, Killer(2)), (Some(Plain(0)), None));
+
+        // Spills take killers like any other tile.
+        assert_eq!(TileType::collide(Killer(2), Spillage(LiquidType::Acid)), (None, None));
+        assert_eq!(TileType::collide(Killer(2), Spillage(LiquidType::Glue)),
+                   (None, Some(Plain(0))));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = Json::Obj(vec![
+            ("n".to_string(), Json::Num(-12)),
+            ("s".to_string(), Json::Str("a \"quoted\" \\ string".to_string())),
+            ("l".to_string(), Json::List(vec![Json::List(vec![]), Json::Obj(vec![])])),
+        ]);
+        assert_eq!(Json::parse(&json.to_string()), Ok(json.clone()));
+        assert_eq!(Json::parse(" { \"n\" : -12 , \"s\":\"a \\\"quoted\\\" \\\\ string\",\
+                                \"l\": [ [], {} ] } "), Ok(json));
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("{} x").is_err());
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        use TileType::*;
+        let tiles = [Plain(0), Plain(3), Permanent, Killer(2), Picker, Centerpiece(1),
+                     Whopper(4), Flask(LiquidType::Acid), Flask(LiquidType::Glue),
+                     Spillage(LiquidType::Acid), Spillage(LiquidType::Glue), Plus, Minus,
+                     Bomb, Clock, Chaos, Converter, Hourglass, Meteor];
+        let mut blk = Block::new_at(3, -1);
+        for (i, &tt) in tiles.iter().enumerate() {
+            blk.tiles.push((i as i16 % 5, -(i as i16 / 5), tt));
+        }
+
+        let back = Block::from_snapshot(&blk.to_snapshot()).unwrap();
+        assert_eq!((back.x, back.y), (blk.x, blk.y));
+        assert_eq!(back.tiles, blk.tiles);
+        assert!(Block::from_snapshot("{\"x\":1,\"y\":1}").is_err());
+    }
+
+    #[test]
+    fn off_grid_is_cut_off() {
+        let mut grid = Grid::new(4, 2);
+        grid.fill_rect(-2, -1, 9, 5, Field::Decoration('x'));
+        grid.color_drawings(-2, -1, 9, 5, 1);
+        grid.draw_rect(3, 1, 4, 4, Pen::Thin);
+        assert_eq!(grid.to_string(), "xxxxx\nxxx┌─\nxxx│x\n");
+        assert_eq!(grid.field_char(-1, 0, false), None);
+        assert_eq!(grid.field_char(0, 3, false), None);
+        assert_eq!(grid.color(5, 0), 0);
+    }
+
+    #[test]
+    fn spill_at_the_edge() {
+        let mut pg = board("a**\n\
+                            *T*\n\
+                            ***\n");
+        let exploded = pg.explode().exploded;
+        assert_eq!(exploded.len(), 9);
+        assert!(pg.tiles.iter().any(|&(x, y, _)| x < 0 || y < 0));
+
+        let mut grid = Grid::new(12, 6);
+        let look = Look {outline: Pen::Thik, ascii: false, plain: false, distinct: false};
+        pg.paint(&mut grid, look);
+        assert_eq!(grid.to_string().lines().count(), 7);
+    }
+
+    fn spilled(pg: &Block, liquid: LiquidType) -> Vec<(i16, i16)> {
+        let mut spilled: Vec<_> = pg.tiles.iter()
+            .filter(|&&(_, _, tt)| tt == TileType::Spillage(liquid))
+            .map(|&(x, y, _)| (x, y))
+            .collect();
+        spilled.sort();
+        spilled
+    }
+
+    #[test]
+    fn spill_shapes() {
+        // The permanent tile is in the way of acid, but not of glue.
+        for &(flask, liquid) in &[('a', LiquidType::Acid), ('g', LiquidType::Glue)] {
+            let mut pg = board(&format!(".....\n\
+                                         .....\n\
+                                         #.{}**\n\
+                                         ..*T*\n\
+                                         ..***\n", flask));
+            pg.explode();
+
+            let mut expect: Vec<_> = liquid.spill_shape().iter()
+                .map(|&(dx, dy)| (2 + dx, 2 + dy))
+                .filter(|&field| field != (0, 2))
+                .collect();
+            expect.sort();
+            assert_eq!(spilled(&pg, liquid), expect, "{:?}", liquid);
+            assert_eq!(pg.at(0, 2), Some(TileType::Permanent));
+        }
+        assert_eq!(LiquidType::Acid.spill_shape().len(), 13);
+        assert_eq!(LiquidType::Glue.spill_shape().len(), 5);
+    }
+
+    #[test]
+    fn converter_neighbors() {
+        use TileType::*;
+        let mut pg = board("...XC\n\
+                            .**V#\n\
+                            .*T*.\n\
+                            %V**.\n\
+                            .P...\n");
+        let exploded = pg.explode().exploded;
+        assert_eq!(exploded.len(), 9);
+
+        // Only the fields above, below and to the sides change, and
+        // neither permanent tiles nor spills do.
+        assert_eq!(pg.at(3, 0), Some(Plain(0)));
+        assert_eq!(pg.at(1, 4), Some(Plain(0)));
+        assert_eq!(pg.at(4, 1), Some(Permanent));
+        assert_eq!(pg.at(0, 3), Some(Spillage(LiquidType::Acid)));
+        assert_eq!(pg.at(4, 0), Some(Centerpiece(1)));
+        assert_eq!(pg.tiles.len(), 5);
+    }
+
+    #[test]
+    fn spills_react() {
+        use TileType::*;
+        use LiquidType::*;
+
+        // Acid neutralizes the glue it lands on, and leaves acid be.
+        let mut pg = board(".....\n\
+                            ..~..\n\
+                            .%a**\n\
+                            ..*T*\n\
+                            ..***\n");
+        pg.explode();
+        assert_eq!(pg.at(2, 1), None);
+        assert_eq!(pg.at(1, 2), Some(Spillage(Acid)));
+        assert_eq!(pg.at(2, 0), Some(Spillage(Acid)));
+
+        // Glue sets on acid, and leaves glue be.
+        let mut pg = board(".....\n\
+                            ..%..\n\
+                            .~g**\n\
+                            ..*T*\n\
+                            ..***\n");
+        pg.explode();
+        assert_eq!(pg.at(2, 1), Some(Plain(0)));
+        assert_eq!(pg.at(1, 2), Some(Spillage(Glue)));
+        assert_eq!(pg.at(2, 2), Some(Spillage(Glue)));
+    }
+
+    #[test]
+    fn block_onto_spills() {
+        use TileType::*;
+        use LiquidType::*;
+
+        // Acid dissolves the tile that lands on it, glue keeps it on
+        // the board in its place, tamed if it's a killer.
+        let pg = board("%~\n");
+        let (blk, pg, splashes) = Block::collide(board("*X\n"), &pg);
+        assert!(blk.tiles.is_empty());
+        assert_eq!(pg.at(0, 0), None);
+        assert_eq!(pg.at(1, 0), Some(Plain(0)));
+        assert_eq!(pg.tiles.len(), 1);
+        assert_eq!(splashes, vec![(0, 0, Acid), (1, 0, Glue)]);
+    }
+}
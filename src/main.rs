@@ -16,1274 +16,4371 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+extern crate grido;
 extern crate ncurses;
 extern crate time;
 extern crate rand;
 
+use grido::*;
 use ncurses as nc;
-use rand::Rng;
-
-#[derive(Copy, Clone, Debug)]
-enum Pen {
-    None,
-    Thin,
-    Thik,
+use rand::{Rng, SeedableRng, StdRng};
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
+
+// Grids are drawn on the terminal with this.
+trait Render {
+    // Print runs of non-transparent fields of the same color, so that
+    // whatever is on screen under the transparent ones stays visible.
+    fn render(&self, x0: i16, y0: i16, ascii: bool);
 }
 
-impl Pen {
-    fn combine(p1: Pen, p2: Pen) -> Pen {
-        match (p1, p2) {
-            (Pen::None, p) => p,
-            (p, Pen::None) => p,
-            (Pen::Thik, _) |
-            (_, Pen::Thik) => Pen::Thik,
-            (Pen::Thin, Pen::Thin) => Pen::Thin,
+impl Render for Grid {
+    fn render(&self, x0: i16, y0: i16, ascii: bool) {
+        let colors = nc::has_colors();
+        for y in 0..self.h+1 {
+            let mut x = 0;
+            while x <= self.w {
+                let start = x;
+                let color = self.color(x, y);
+                let mut run = String::new();
+                while let Some(c) = self.field_char(x, y, ascii) {
+                    run.push(c);
+                    x += 1;
+                    if x <= self.w && self.color(x, y) != color {
+                        break;
+                    }
+                }
+
+                if !run.is_empty() {
+                    let color = if colors { color } else { 0 };
+                    if color != 0 {
+                        nc::attron(nc::COLOR_PAIR(color));
+                    }
+                    nc::mvprintw(y0 as i32 + y as i32,
+                                 x0 as i32 + start as i32, &run);
+                    if color != 0 {
+                        nc::attroff(nc::COLOR_PAIR(color));
+                    }
+                } else {
+                    x += 1;
+                }
+            }
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-enum Direction {
-    Up,
-    Right,
-    Down,
-    Left,
-}
+// How many points it takes to get to the next level.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum LevelCurve {
+    // Each level takes 100 points more than the one before it.
+    // Level 1 is reached at 1 point, level 2 at 101, level 3 at 301,
+    // level 4 at 601 and so on.
+    Quadratic,
 
-#[derive(Copy, Clone, Debug)]
-struct FieldDrawing {
-    up: Pen,
-    right: Pen,
-    down: Pen,
-    left: Pen,
+    // Like Quadratic, but 150 points more each level.
+    Steep,
+
+    // Each level takes the same 300 points.
+    Linear,
 }
 
-impl FieldDrawing {
-    fn new_from(d: Direction, p: Pen) -> FieldDrawing {
-        let mut up = Pen::None;
-        let mut right = Pen::None;
-        let mut down = Pen::None;
-        let mut left = Pen::None;
+impl Choice for LevelCurve {
+    fn all() -> &'static [LevelCurve] {
+        static ALL: [LevelCurve; 3] = [LevelCurve::Quadratic,
+                                       LevelCurve::Steep,
+                                       LevelCurve::Linear];
+        &ALL
+    }
 
-        match d {
-            Direction::Up    => up = p,
-            Direction::Right => right = p,
-            Direction::Down  => down = p,
-            Direction::Left  => left = p,
+    fn name(&self) -> &'static str {
+        match *self {
+            LevelCurve::Quadratic => "quadratic",
+            LevelCurve::Steep => "steep",
+            LevelCurve::Linear => "linear",
         }
+    }
+}
 
-        FieldDrawing {up: up, right: right, down: down, left: left}
+impl LevelCurve {
+    // The points that it takes to get from level lvl - 1 to lvl.
+    fn step(&self, lvl: u8) -> u32 {
+        match *self {
+            LevelCurve::Quadratic => lvl as u32 * 100,
+            LevelCurve::Steep => lvl as u32 * 150,
+            LevelCurve::Linear => 300,
+        }
     }
 
-    fn combine(&self, other: FieldDrawing) -> FieldDrawing {
-        FieldDrawing {up:    Pen::combine(self.up, other.up),
-                      right: Pen::combine(self.right, other.right),
-                      down:  Pen::combine(self.down, other.down),
-                      left:  Pen::combine(self.left, other.left)}
+    fn level(&self, score: u32) -> u8 {
+        let mut base: u32 = 0;
+        let mut lvl: u8 = 0;
+        while base < score && lvl < u8::MAX {
+            lvl += 1;
+            base = base.saturating_add(self.step(lvl));
+        }
+        lvl
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-enum Field {
-    None,
-    Decoration(char),
-    Drawing(FieldDrawing),
-}
+// Where play shows the side panel with the gauges and the score.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum HudLayout {
+    // Side if the terminal is wide enough for it, else Below.
+    Auto,
 
-#[derive(Debug)]
-struct Grid {
-    w: i16,
-    h: i16,
+    // Right of the previews.
+    Side,
 
-    grid: Vec<Field>,
+    // In columns under the playground, for tall and narrow terminals.
+    Below,
 }
 
-impl Grid {
-    fn new(w: i16, h: i16) -> Grid {
-        assert!(w >= 0);
-        assert!(h >= 0);
-
-        let mut grid = Vec::new();
-        for _ in 0.. (w + 1) * (h + 1) {
-            grid.push(Field::None);
-        }
-
-        Grid {w:w as i16, h:h as i16, grid:grid}
+impl Choice for HudLayout {
+    fn all() -> &'static [HudLayout] {
+        static ALL: [HudLayout; 3] = [HudLayout::Auto, HudLayout::Side, HudLayout::Below];
+        &ALL
     }
 
-    fn field_idx(&self, x: i16, y: i16) -> usize {
-        y as usize * (self.w + 1) as usize + x as usize
-    }
-
-    fn field_mut(&mut self, x: i16, y: i16) -> &mut Field {
-        let idx = self.field_idx(x, y);
-        &mut self.grid[idx]
+    fn name(&self) -> &'static str {
+        match *self {
+            HudLayout::Auto => "auto",
+            HudLayout::Side => "side",
+            HudLayout::Below => "below",
+        }
     }
+}
 
-    fn paint(&mut self, x: i16, y: i16, d: Direction, p: Pen) {
-        let f = self.field_mut(x, y);
-        *f = match *f {
-            Field::None |
-            Field::Decoration(..)
-                => Field::Drawing(FieldDrawing::new_from(d, p)),
-
-            Field::Drawing(dw)
-                => Field::Drawing(dw.combine(FieldDrawing::new_from(d, p))),
+impl HudLayout {
+    // The layout to use for a pgw x pgh playground in the terminal as
+    // it is now.  Never Auto.
+    fn resolve(self, pgw: i16, pgh: i16) -> HudLayout {
+        match self {
+            HudLayout::Auto => {
+                let (cols, _) = terminal_size();
+                let (side_cols, _) = screen_size(pgw, pgh, HudLayout::Side);
+                if cols >= side_cols { HudLayout::Side } else { HudLayout::Below }
+            },
+            layout => layout,
         }
     }
+}
 
-    fn clear(&mut self, x: i16, y: i16, w: i16, h: i16) {
-        assert!(w >= 0);
-        assert!(h >= 0);
-
-        // Inner portion can be wiped simply.
-        for xx in x .. x+w {
-            for yy in y .. y+h {
-                // Left or right edge.
-                let ex0 = xx == x;
-                let ex1 = xx == x+w-1;
-                let ex = ex0 || ex1;
-
-                // Upper or lower edge.
-                let ey0 = yy == y;
-                let ey1 = yy == y+h-1;
-                let ey = ey0 || ey1;
+// The side panel has this many rows.  Below the playground, they are
+// split into columns of HUD_BELOW_ROWS, each HUD_COLUMN_WIDTH wide.
+const HUD_ROWS: usize = 18;
+const HUD_BELOW_ROWS: usize = 6;
+const HUD_COLUMN_WIDTH: i32 = 16;
 
-                let f = self.field_mut(xx, yy);
+// What's drawn on the empty parts of the playground.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Background {
+    None,
 
-                if !ex && !ey {
-                    // Non-edge tile.
-                    *f = Field::None;
-                } else {
-                    if ex {
-                        if let Field::Drawing(ref mut dw) = *f {
-                            if !ey {
-                                dw.down = Pen::None;
-                                dw.up = Pen::None;
-                            }
+    // Diagonal rows of dots.
+    Dots,
 
-                            if ex0 {
-                                dw.right = Pen::None;
-                            } else {
-                                dw.left = Pen::None;
-                            }
-                        } else {
-                            *f = Field::None;
-                        }
-                    }
+    // A dot at each corner of the fields that tiles take.
+    Grid,
+}
 
-                    if ey {
-                        // Upper or lower edge.  We erase the
-                        // horizontal and the inner (down or up) arm.
-                        if let Field::Drawing(ref mut dw) = *f {
-                            if !ex {
-                                dw.left = Pen::None;
-                                dw.right = Pen::None;
-                            }
+impl Choice for Background {
+    fn all() -> &'static [Background] {
+        static ALL: [Background; 3] = [Background::None,
+                                       Background::Dots,
+                                       Background::Grid];
+        &ALL
+    }
 
-                            if ey0 {
-                                dw.down = Pen::None;
-                            } else {
-                                dw.up = Pen::None;
-                            }
-                        } else {
-                            *f = Field::None;
-                        }
-                    }
-                }
-            }
+    fn name(&self) -> &'static str {
+        match *self {
+            Background::None => "none",
+            Background::Dots => "dots",
+            Background::Grid => "grid",
         }
     }
+}
 
-    fn paint_decoration(&mut self, x: i16, y: i16, s: &str) {
-        let mut n = 0;
-        for c in s.chars() {
-            if c != '\0' {
-                *self.field_mut(x+n, y) = Field::Decoration(c);
+fn paint_background(grid: &mut Grid, background: Background, look: Look) {
+    let background = if look.plain { Background::None } else { background };
+    for xx in 0..grid.w {
+        for yy in 0..grid.h {
+            let dot = match background {
+                Background::None => None,
+                Background::Dots if xx % 3 == yy % 3 => Some("."),
+                Background::Grid if xx % 4 == 0 && yy % 2 == 0 => Some("·"),
+                _ => None,
+            };
+            if let Some(dot) = dot {
+                grid.paint_decoration(xx, yy, dot);
             }
-            n += 1;
         }
     }
+    let (w, h) = (grid.w, grid.h);
+    grid.color_rect(0, 0, w, h, PAIR_DOTS);
+}
 
-    fn paint_wall(&mut self, x0: i16, y0: i16, len: i16,
-                  d: Direction, inclusive: bool, p: Pen) {
-        assert!(len >= 0);
-
-        let (d1, d2, dx, dy) = match d {
-            Direction::Right => (Direction::Right, Direction::Left, 1, 0),
-            Direction::Left => (Direction::Left, Direction::Right, -1, 0),
-            Direction::Down => (Direction::Down, Direction::Up, 0, 1),
-            Direction::Up => (Direction::Up, Direction::Down, 0, -1),
-        };
-
-        let x1 = x0 + len * dx;
-        let y1 = y0 + len * dy;
-
-        if inclusive {
-            self.paint(x0, y0, d1, p);
-        }
-        for i in 1..len {
-            let xx = x0 + i * dx;
-            let yy = y0 + i * dy;
-            self.paint(xx, yy, d2, p);
-            self.paint(xx, yy, d1, p);
-        }
-        if inclusive {
-            self.paint(x1, y1, d2, p);
-        }
-    }
-
-    fn render_field_drawing(dw: FieldDrawing) -> &'static str {
-        match (dw.up, dw.right, dw.down, dw.left) {
-            (Pen::None, Pen::None, Pen::None, Pen::None) => " ",
-
-            (Pen::None, Pen::None, Pen::None, Pen::Thin) => "╴",
-            (Pen::None, Pen::None, Pen::Thin, Pen::None) => "╷",
-            (Pen::None, Pen::None, Pen::Thin, Pen::Thin) => "┐",
-            (Pen::None, Pen::Thin, Pen::None, Pen::None) => "╶",
-            (Pen::None, Pen::Thin, Pen::None, Pen::Thin) => "─",
-            (Pen::None, Pen::Thin, Pen::Thin, Pen::None) => "┌",
-            (Pen::None, Pen::Thin, Pen::Thin, Pen::Thin) => "┬",
-            (Pen::Thin, Pen::None, Pen::None, Pen::None) => "╵",
-            (Pen::Thin, Pen::None, Pen::None, Pen::Thin) => "┘",
-            (Pen::Thin, Pen::None, Pen::Thin, Pen::None) => "│",
-            (Pen::Thin, Pen::None, Pen::Thin, Pen::Thin) => "┤",
-            (Pen::Thin, Pen::Thin, Pen::None, Pen::None) => "└",
-            (Pen::Thin, Pen::Thin, Pen::None, Pen::Thin) => "┴",
-            (Pen::Thin, Pen::Thin, Pen::Thin, Pen::None) => "├",
-            (Pen::Thin, Pen::Thin, Pen::Thin, Pen::Thin) => "┼",
-
-            (Pen::None, Pen::None, Pen::None, Pen::Thik) => "╸",
-            (Pen::None, Pen::None, Pen::Thik, Pen::None) => "╻",
-            (Pen::None, Pen::None, Pen::Thik, Pen::Thik) => "┓",
-            (Pen::None, Pen::Thik, Pen::None, Pen::None) => "╺",
-            (Pen::None, Pen::Thik, Pen::None, Pen::Thik) => "━",
-            (Pen::None, Pen::Thik, Pen::Thik, Pen::None) => "┏",
-            (Pen::None, Pen::Thik, Pen::Thik, Pen::Thik) => "┳",
-            (Pen::Thik, Pen::None, Pen::None, Pen::None) => "╹",
-            (Pen::Thik, Pen::None, Pen::None, Pen::Thik) => "┛",
-            (Pen::Thik, Pen::None, Pen::Thik, Pen::None) => "┃",
-            (Pen::Thik, Pen::None, Pen::Thik, Pen::Thik) => "┫",
-            (Pen::Thik, Pen::Thik, Pen::None, Pen::None) => "┗",
-            (Pen::Thik, Pen::Thik, Pen::None, Pen::Thik) => "┻",
-            (Pen::Thik, Pen::Thik, Pen::Thik, Pen::None) => "┣",
-            (Pen::Thik, Pen::Thik, Pen::Thik, Pen::Thik) => "╋",
-
-            (Pen::None, Pen::None, Pen::Thik, Pen::Thin) => "┒",
-            (Pen::None, Pen::None, Pen::Thin, Pen::Thik) => "┑",
-            (Pen::None, Pen::Thik, Pen::None, Pen::Thin) => "╼",
-            (Pen::None, Pen::Thik, Pen::Thik, Pen::Thin) => "┲",
-            (Pen::None, Pen::Thik, Pen::Thin, Pen::None) => "┍",
-            (Pen::None, Pen::Thik, Pen::Thin, Pen::Thik) => "┯",
-            (Pen::None, Pen::Thik, Pen::Thin, Pen::Thin) => "┮",
-            (Pen::None, Pen::Thin, Pen::None, Pen::Thik) => "╾",
-            (Pen::None, Pen::Thin, Pen::Thik, Pen::None) => "┎",
-            (Pen::None, Pen::Thin, Pen::Thik, Pen::Thik) => "┱",
-            (Pen::None, Pen::Thin, Pen::Thik, Pen::Thin) => "┰",
-            (Pen::None, Pen::Thin, Pen::Thin, Pen::Thik) => "┭",
-            (Pen::Thik, Pen::None, Pen::None, Pen::Thin) => "┚",
-            (Pen::Thik, Pen::None, Pen::Thik, Pen::Thin) => "┨",
-            (Pen::Thik, Pen::None, Pen::Thin, Pen::None) => "╿",
-            (Pen::Thik, Pen::None, Pen::Thin, Pen::Thik) => "┩",
-            (Pen::Thik, Pen::None, Pen::Thin, Pen::Thin) => "┦",
-            (Pen::Thik, Pen::Thik, Pen::None, Pen::Thin) => "┺",
-            (Pen::Thik, Pen::Thik, Pen::Thik, Pen::Thin) => "╊",
-            (Pen::Thik, Pen::Thik, Pen::Thin, Pen::None) => "┡",
-            (Pen::Thik, Pen::Thik, Pen::Thin, Pen::Thik) => "╇",
-            (Pen::Thik, Pen::Thik, Pen::Thin, Pen::Thin) => "╄",
-            (Pen::Thik, Pen::Thin, Pen::None, Pen::None) => "┖",
-            (Pen::Thik, Pen::Thin, Pen::None, Pen::Thik) => "┹",
-            (Pen::Thik, Pen::Thin, Pen::None, Pen::Thin) => "┸",
-            (Pen::Thik, Pen::Thin, Pen::Thik, Pen::None) => "┠",
-            (Pen::Thik, Pen::Thin, Pen::Thik, Pen::Thik) => "╉",
-            (Pen::Thik, Pen::Thin, Pen::Thik, Pen::Thin) => "╂",
-            (Pen::Thik, Pen::Thin, Pen::Thin, Pen::None) => "┞",
-            (Pen::Thik, Pen::Thin, Pen::Thin, Pen::Thik) => "╃",
-            (Pen::Thik, Pen::Thin, Pen::Thin, Pen::Thin) => "╀",
-            (Pen::Thin, Pen::None, Pen::None, Pen::Thik) => "┙",
-            (Pen::Thin, Pen::None, Pen::Thik, Pen::None) => "╽",
-            (Pen::Thin, Pen::None, Pen::Thik, Pen::Thik) => "┪",
-            (Pen::Thin, Pen::None, Pen::Thik, Pen::Thin) => "┧",
-            (Pen::Thin, Pen::None, Pen::Thin, Pen::Thik) => "┥",
-            (Pen::Thin, Pen::Thik, Pen::None, Pen::None) => "┕",
-            (Pen::Thin, Pen::Thik, Pen::None, Pen::Thik) => "┷",
-            (Pen::Thin, Pen::Thik, Pen::None, Pen::Thin) => "┶",
-            (Pen::Thin, Pen::Thik, Pen::Thik, Pen::None) => "┢",
-            (Pen::Thin, Pen::Thik, Pen::Thik, Pen::Thik) => "╈",
-            (Pen::Thin, Pen::Thik, Pen::Thin, Pen::Thin) => "┾",
-            (Pen::Thin, Pen::Thik, Pen::Thik, Pen::Thin) => "╆",
-            (Pen::Thin, Pen::Thik, Pen::Thin, Pen::None) => "┝",
-            (Pen::Thin, Pen::Thik, Pen::Thin, Pen::Thik) => "┿",
-            (Pen::Thin, Pen::Thin, Pen::None, Pen::Thik) => "┵",
-            (Pen::Thin, Pen::Thin, Pen::Thik, Pen::None) => "┟",
-            (Pen::Thin, Pen::Thin, Pen::Thik, Pen::Thik) => "╅",
-            (Pen::Thin, Pen::Thin, Pen::Thik, Pen::Thin) => "╁",
-            (Pen::Thin, Pen::Thin, Pen::Thin, Pen::Thik) => "┽",
-        }
-    }
-
-    fn render(&self, x0: i16, y0: i16) {
-        for y in 0..self.h+1 {
-            for x in 0..self.w+1 {
-                match self.grid[self.field_idx(x, y)] {
-                    Field::None => {
-                    },
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Difficulty {
+    // Slower gauges, and tiles show up a couple of levels late.
+    Easy,
+    Normal,
+
+    // Faster gauges, tiles come a level early, and the board starts
+    // with some plain tiles on it.
+    Hard,
+}
 
-                    Field::Decoration(c) => {
-                        if c != '\0' {
-                            nc::mvprintw(y0 as i32 + y as i32,
-                                         x0 as i32 + x as i32,
-                                         &c.to_string());
-                        }
-                    },
+impl Choice for Difficulty {
+    fn all() -> &'static [Difficulty] {
+        static ALL: [Difficulty; 3] = [Difficulty::Easy,
+                                       Difficulty::Normal,
+                                       Difficulty::Hard];
+        &ALL
+    }
 
-                    Field::Drawing(dw) => {
-                        nc::mvprintw(y0 as i32 + y as i32,
-                                     x0 as i32 + x as i32,
-                                     Grid::render_field_drawing(dw));
-                    },
-                };
-            }
+    fn name(&self) -> &'static str {
+        match *self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
         }
     }
 }
 
+impl Difficulty {
+    // The level that decides which tiles can be drawn.
+    fn tile_level(&self, lvl: u8) -> u8 {
+        match *self {
+            Difficulty::Easy => lvl.saturating_sub(2),
+            Difficulty::Normal => lvl,
+            Difficulty::Hard => lvl.saturating_add(1),
+        }
+    }
+
+    fn drop_interval(&self, lvl: u8, start_ms: i64) -> i64 {
+        let percent = match *self {
+            Difficulty::Easy => 150,
+            Difficulty::Normal => 100,
+            Difficulty::Hard => 75,
+        };
+        drop_interval(lvl, start_ms) * percent / 100
+    }
 
-fn level(score: u32) -> u8 {
-    let mut base: u32 = 0;
-    let mut lvl: u8 = 0;
-    while base < score {
-        lvl += 1;
-        base += lvl as u32 * 100;
+    fn start_tiles(&self) -> u32 {
+        match *self {
+            Difficulty::Hard => 8,
+            _ => 0,
+        }
     }
-    lvl
 }
 
+// The rules for when a game ends.
 #[derive(Copy, Clone, PartialEq, Debug)]
-enum LiquidType {
-    Acid,
-    Glue,
+enum GameMode {
+    // Over once a new block doesn't fit.
+    Classic,
+
+    // Endless.  Whatever is in the way of a new block is swept off
+    // the board, and the game only ends when the player quits.
+    Zen,
+
+    // Over after the given milliseconds of play, or when a new block
+    // doesn't fit, whichever comes first.
+    Blitz(i64),
 }
 
+// How long a blitz game lasts.
+const BLITZ_MS: i64 = 120000;
+
+// How the shapes of new blocks are picked.
 #[derive(Copy, Clone, PartialEq, Debug)]
-enum TileType {
-    Plain(u8),
-    Permanent,
-    Killer(u8),
-    Picker,
-    Centerpiece(u8),
-    Whopper(u8),
-    Flask(LiquidType),
-    Spillage(LiquidType),
-    Plus,
-    Minus,
+enum Randomizer {
+    // Each one at random, see Random.
+    Pure,
+
+    // All of them in turn, in a random order, see Bag.
+    Bag,
 }
 
-#[derive(PartialEq,Debug)]
-enum ExplodeAction {
-    Remove,
-    Convert(TileType),
-    Spill(LiquidType),
-    Plus,
-    Minus,
-    Complex(Box<ExplodeAction>, Box<ExplodeAction>),
+impl Choice for Randomizer {
+    fn all() -> &'static [Randomizer] {
+        static ALL: [Randomizer; 2] = [Randomizer::Pure, Randomizer::Bag];
+        &ALL
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Randomizer::Pure => "pure",
+            Randomizer::Bag => "bag",
+        }
+    }
 }
 
-impl TileType {
-    fn new_random(score: u32) -> TileType {
-        let lvl = level(score);
-        let mut rng = rand::thread_rng();
-        loop {
-            match rng.gen_range(0, 33) {
-                0..=20 => return TileType::Plain(0),
+impl Choice for GameMode {
+    fn all() -> &'static [GameMode] {
+        static ALL: [GameMode; 3] = [GameMode::Classic, GameMode::Zen,
+                                     GameMode::Blitz(BLITZ_MS)];
+        &ALL
+    }
 
-                21..=23 => return TileType::Picker,
+    fn name(&self) -> &'static str {
+        match *self {
+            GameMode::Classic => "classic",
+            GameMode::Zen => "zen",
+            GameMode::Blitz(_) => "blitz",
+        }
+    }
+}
 
-                24 if lvl >= 1
-                    => return if rng.gen() { TileType::Minus }
-                              else { TileType::Plus },
+// For how long an exploded hourglass slows the gauges down, and how
+// many times longer they take meanwhile.
+const SLOW_MS: i64 = 8000;
+const SLOW_FACTOR: i64 = 2;
 
-                25..=26 if lvl >= 2
-                    => return TileType::Plain(1 + rng.gen_range(0, lvl)),
+// How many tiles an exploded meteor rains down, and how far apart
+// they land.
+const METEOR_TILES: usize = 3;
+const METEOR_MS: i64 = 600;
 
-                27 if lvl >= 3
-                    => return TileType::Flask(if rng.gen() { LiquidType::Acid }
-                                              else { LiquidType::Glue }),
+#[derive(Debug)]
+struct Particle {
+    x: f32,
+    y: f32,
+    face: String,
+    start: time::SteadyTime,
+    ttl: u32,
+    color: i16,
 
-                28 if lvl >= 4
-                    => return TileType::Killer(1 + rng.gen_range(0, lvl / 8 + 1)),
+    // Where the particle started, and how many fields a second it
+    // moves from there.
+    origin: (f32, f32),
+    velocity: (f32, f32),
 
+    // An animated particle shows these faces in turn over its life,
+    // instead of face.  Faces can span several lines.
+    frames: Vec<String>,
+    frame: usize,
+}
 
-                29..=30 if lvl >= 5
-                    => return TileType::Centerpiece(1 + rng.gen_range(0, lvl / 4 + 1)),
+// How far into its life a moving particle starts to fade.
+const PARTICLE_FADE: f32 = 0.75;
 
-                31 if lvl >= 6
-                    => return TileType::Whopper(1 + rng.gen_range(0, lvl / 4 + 1)),
+// How many rings a burst spreads out to.
+const BURST_RINGS: usize = 3;
 
-                32 if lvl >= 8
-                    => if rng.gen() { return TileType::Permanent },
+impl Particle {
+    fn new(x: f32, y: f32, face: String, ttl: u32) -> Particle {
+        Particle {x, y, face, start: time::SteadyTime::now(), ttl, color: 0,
+                  origin: (x, y), velocity: (0., 0.), frames: Vec::new(), frame: 0}
+    }
 
-                _ => {},
+    // The face in rings of stars that spread out from it, centered on
+    // x, y.  Within the rings, the board shows through.
+    fn burst(x: f32, y: f32, face: String, ttl: u32) -> Particle {
+        let len = face.chars().count();
+        let mut frames = vec![face.clone()];
+        for r in 1..BURST_RINGS + 1 {
+            let w = len + 4 * r;
+            let edge: String = (0..w).map(|c| if c % 2 == 0 { '*' } else { '\0' }).collect();
+            let side = format!("*{}*", "\0".repeat(w - 2));
+            let gap = "\0".repeat(2 * r - 1);
+
+            let mut lines = vec![edge.clone()];
+            for _ in 1..r {
+                lines.push(side.clone());
             }
+            lines.push(format!("*{}{}{}*", gap, face, gap));
+            for _ in 1..r {
+                lines.push(side.clone());
+            }
+            lines.push(edge);
+            frames.push(lines.join("\n"));
         }
-    }
-
-    fn render(&self) -> &'static str {
-        match *self {
-            TileType::Permanent               => " ✖ ",
-            TileType::Picker                  => "[ ]",
-            TileType::Flask(LiquidType::Glue) => " ▿ ",
-            TileType::Flask(LiquidType::Acid) => " ▴ ",
-            TileType::Plus                    => " + ",
-            TileType::Minus                   => " - ",
-
-            TileType::Plain(n) => match n {
-                0 => "   ",
-                1 => " • ",
-                2 => " •²",
-                3 => " •³",
-                4 => " •⁴",
-                5 => " •⁵",
-                6 => " •⁶",
-                7 => " •⁷",
-                8 => " •⁸",
-                9 => " •⁹",
-                _ => " •ⁿ",
-            },
 
-            TileType::Killer(n) => match n {
-                0 => " ↯⁰",
-                1 => " ↯ ",
-                2 => " ↯²",
-                3 => " ↯³",
-                4 => " ↯⁴",
-                5 => " ↯⁵",
-                6 => " ↯⁶",
-                7 => " ↯⁷",
-                8 => " ↯⁸",
-                9 => " ↯⁹",
-                _ => " ↯ⁿ",
-            },
+        let mut p = Particle::new(x, y, face, ttl);
+        p.frames = frames;
+        p.update(false);
+        p
+    }
 
-            TileType::Centerpiece(n) => match n {
-                0 => " ◉⁰",
-                1 => " ◉ ",
-                2 => " ◉²",
-                3 => " ◉³",
-                4 => " ◉⁴",
-                5 => " ◉⁵",
-                6 => " ◉⁶",
-                7 => " ◉⁷",
-                8 => " ◉⁸",
-                9 => " ◉⁹",
-                _ => " ◉ⁿ",
-            },
+    // The same particle, but drifting away and fading out.
+    fn moving(mut self, vx: f32, vy: f32) -> Particle {
+        self.velocity = (vx, vy);
+        self
+    }
 
-            TileType::Whopper(n) => match n {
-                0 => " ✱⁰",
-                1 => " ✱ ",
-                2 => " ✱²",
-                3 => " ✱³",
-                4 => " ✱⁴",
-                5 => " ✱⁵",
-                6 => " ✱⁶",
-                7 => " ✱⁷",
-                8 => " ✱⁸",
-                9 => " ✱⁹",
-                _ => " ✱ⁿ",
-            },
+    // The same particle in the given color pair, see Theme.
+    fn colored(mut self, color: i16) -> Particle {
+        self.color = color;
+        self
+    }
 
-            // Spills are formatted differently.
-            TileType::Spillage(LiquidType::Glue) => "▿",
-            TileType::Spillage(LiquidType::Acid) => "▴",
-        }
+    fn age(&self) -> f32 {
+        (time::SteadyTime::now() - self.start).num_milliseconds() as f32 / 1000.
     }
 
-    fn drop(&self) -> Option<TileType> {
-        match *self {
-            TileType::Killer(_) => Some(TileType::Plain(0)),
-            tt => Some(tt),
+    // With reduced motion, particles stay where they started, and
+    // animated ones show their last frame all along.
+    fn update(&mut self, reduce_motion: bool) {
+        let t = if reduce_motion { 0. } else { self.age() };
+        self.x = self.origin.0 + self.velocity.0 * t;
+        self.y = self.origin.1 + self.velocity.1 * t;
+
+        if !self.frames.is_empty() {
+            let n = self.frames.len();
+            self.frame = if reduce_motion {
+                n - 1
+            } else {
+                cmp::min((t * 1000. / self.ttl as f32 * n as f32) as usize, n - 1)
+            };
+
+            // Frames grow around the middle.
+            let face = &self.frames[self.frame];
+            let w = face.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+            let h = face.lines().count();
+            self.x -= (w / 2) as f32;
+            self.y -= (h / 2) as f32;
         }
     }
 
-    fn explode(&self) -> ExplodeAction {
-        use ExplodeAction::*;
-        match *self {
-            TileType::Plain(0) => Remove,
-            TileType::Plain(n) => Convert(TileType::Plain(n - 1)),
-
-            TileType::Centerpiece(1) => Remove,
-            TileType::Centerpiece(n) => Convert(TileType::Centerpiece(n - 1)),
-
-            TileType::Whopper(n) => Convert(TileType::Centerpiece(n)),
-
-            TileType::Flask(liquid) => Spill(liquid),
-
-            TileType::Plus => Complex(Box::new(Remove), Box::new(Plus)),
-            TileType::Minus => Complex(Box::new(Remove), Box::new(Minus)),
+    fn paint(&self, grid: &mut Grid) {
+        let face = if self.frames.is_empty() { &self.face } else { &self.frames[self.frame] };
+        let fading = self.velocity != (0., 0.)
+            && self.age() * 1000. > PARTICLE_FADE * self.ttl as f32;
+
+        for (i, line) in face.lines().enumerate() {
+            // Whatever drifted off the grid is gone.
+            let len = line.chars().count() as i16;
+            let (x, y) = (self.x.floor() as i16, self.y.floor() as i16 + i as i16);
+            if x < 0 || y < 0 || x + len > grid.w + 1 || y > grid.h {
+                continue;
+            }
 
-            _ => Remove,
+            if fading {
+                let line: String = line.chars()
+                    .map(|c| if c == ' ' || c == '\0' { c } else { '·' })
+                    .collect();
+                grid.paint_decoration(x, y, &line);
+            } else {
+                grid.paint_decoration(x, y, line);
+            }
+            for (j, c) in line.chars().enumerate() {
+                if c != '\0' {
+                    grid.color_rect(x + j as i16, y, 1, 1, self.color);
+                }
+            }
         }
     }
 
-    fn is_plain(&self) -> bool {
-        match *self {
-            TileType::Plain(_) |
-            TileType::Flask(..) |
-            TileType::Plus |
-            TileType::Minus => true,
-            _ => false,
-        }
+    fn dead(&self) -> bool {
+        let ttl = time::Duration::milliseconds(self.ttl as i64);
+        time::SteadyTime::now() - self.start > ttl
     }
+}
 
-    fn is_solid(&self) -> bool {
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum PopupStyle {
+    Plain,
+    Framed,
+
+    // The plain text put in the popup template of the settings, where
+    // it has {}.
+    Custom,
+}
+
+impl PopupStyle {
+    fn score(&self, bonus: u32, template: &str) -> String {
         match *self {
-            TileType::Spillage(_) => false,
-            _ => true,
+            PopupStyle::Plain => format!("{}", bonus),
+            PopupStyle::Framed => format!("«+{}»", bonus),
+            PopupStyle::Custom => template.replace("{}", &bonus.to_string()),
         }
     }
 
-    fn explodes(&self, tt2: TileType) -> bool {
-        match *self {
-            // Plain tiles explode other plain tiles (not e.g.
-            // Centerpieces).
-            tt1 if tt1.is_plain() => tt2.is_plain(),
-
-            // If Centerpieces is a centerpiece, it explodes other
-            // plain tiles or Centerpieces.
-            TileType::Centerpiece(_) => {
-                if let TileType::Centerpiece(_) = tt2 {
-                    true
-                } else {
-                    tt2.is_plain()
-                }
-            },
-
-            // If Whopper is a centerpiece, it explodes other plain
-            // tiles, Centerpieces and Whoppers.
-            TileType::Whopper(_) => {
-                match tt2 {
-                    TileType::Centerpiece(_) |
-                    TileType::Whopper(_) => true,
-                    _ => tt2.is_plain(),
-                }
+    fn multiplier(&self, dmult: i32, template: &str) -> String {
+        match (*self, dmult >= 0) {
+            (PopupStyle::Plain, true) => format!("+x{}", dmult),
+            (PopupStyle::Plain, false) => format!("-x{}", -dmult),
+            (PopupStyle::Framed, true) => format!("▲x{}", dmult),
+            (PopupStyle::Framed, false) => format!("▼x{}", -dmult),
+            (PopupStyle::Custom, _) => {
+                let plain = PopupStyle::Plain.multiplier(dmult, template);
+                template.replace("{}", &plain)
             },
-
-            // The rest doesn't explode.
-            _ => false,
         }
     }
+}
 
-    fn collide(t1: TileType, t2: TileType) -> (Option<TileType>, Option<TileType>) {
-        match (t1, t2) {
-            // Liquids are never on the block.
-            (_, TileType::Spillage(LiquidType::Acid)) => (None, None),
-            (_, TileType::Spillage(LiquidType::Glue)) => (None, t1.drop()),
-
-            (TileType::Picker, _) => (t2.drop(), None),
-            (_, TileType::Picker) => (None, t1.drop()),
+// A setting that takes one of a fixed set of values, each of which
+// has a name that's used in the configuration file.
+trait Choice: Copy + PartialEq + 'static {
+    fn all() -> &'static [Self];
+    fn name(&self) -> &'static str;
 
-            (TileType::Killer(1), _) => (None, None),
-            (TileType::Killer(n), _) => (Some(TileType::Killer(n - 1)), None),
+    fn from_name(name: &str) -> Option<Self> {
+        Self::all().iter().cloned().find(|c| c.name() == name)
+    }
 
-            (_, TileType::Killer(1)) => (None, None),
-            (_, TileType::Killer(n)) => (None, Some(TileType::Killer(n - 1))),
+    fn next(&self) -> Self {
+        let all = Self::all();
+        let i = all.iter().position(|c| c == self).unwrap_or(0);
+        all[(i + 1) % all.len()]
+    }
+}
 
-            (m, n) => (Some(m), Some(n)),
-        }
+impl Choice for PopupStyle {
+    fn all() -> &'static [PopupStyle] {
+        static ALL: [PopupStyle; 3] = [PopupStyle::Plain, PopupStyle::Framed,
+                                       PopupStyle::Custom];
+        &ALL
     }
 
-    fn collides(t1: TileType, t2: TileType) -> bool {
-        match (t1, t2) {
-            _ => true,
+    fn name(&self) -> &'static str {
+        match *self {
+            PopupStyle::Plain => "plain",
+            PopupStyle::Framed => "framed",
+            PopupStyle::Custom => "custom",
         }
     }
+}
 
-    fn explode_shape(&self) -> &'static [(i16, i16)] {
-        match *self {
-            TileType::Whopper(_) => {
-                static SHAPE:[(i16, i16); 25] = [(-2, -2), (-1, -2), (0, -2), (1, -2), (2, -2),
-                                                 (-2, -1), (-1, -1), (0, -1), (1, -1), (2, -1),
-                                                 (-2,  0), (-1,  0), (0,  0), (1,  0), (2,  0),
-                                                 (-2,  1), (-1,  1), (0,  1), (1,  1), (2,  1),
-                                                 (-2,  2), (-1,  2), (0,  2), (1,  2), (2,  2)];
-                &SHAPE
-            },
+// What happens to players who keep rotating and swapping blocks
+// instead of dropping them.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum StallPenalty {
+    Off,
+    Garbage,
+    Hurry,
+}
 
-            _ => {
-                static SHAPE:[(i16, i16); 9] = [(-1, -1), (0, -1), (1, -1),
-                                                (-1,  0), (0,  0), (1,  0),
-                                                (-1,  1), (0,  1), (1,  1)];
-                &SHAPE
-            },
-        }
+impl Choice for StallPenalty {
+    fn all() -> &'static [StallPenalty] {
+        static ALL: [StallPenalty; 3] = [StallPenalty::Off,
+                                         StallPenalty::Garbage,
+                                         StallPenalty::Hurry];
+        &ALL
     }
 
-    fn bonus(&self) -> u32 {
+    fn name(&self) -> &'static str {
         match *self {
-            TileType::Plain(n) => n as u32 + 1,
-            TileType::Centerpiece(n) => 10 * n as u32,
-            TileType::Whopper(_) => 30,
-            _ => 1,
+            StallPenalty::Off => "off",
+            StallPenalty::Garbage => "garbage",
+            StallPenalty::Hurry => "hurry",
         }
     }
 }
 
-#[derive(Debug)]
-struct Block {
-    x: i16,
-    y: i16,
-    tiles: Vec<(i16, i16, TileType)>,
+fn on_off(b: bool) -> &'static str {
+    if b { "on" } else { "off" }
 }
 
-impl Block {
-    fn new() -> Block {
-        Block::new_at(0, 0)
-    }
+// Whether tiles explode as soon as a 3x3 forms, or only when the
+// player says so.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Detonation {
+    Auto,
+    Manual,
+}
 
-    fn new_at(x: i16, y: i16) -> Block {
-        Block {x: x, y: y, tiles:vec![]}
+impl Choice for Detonation {
+    fn all() -> &'static [Detonation] {
+        static ALL: [Detonation; 2] = [Detonation::Auto, Detonation::Manual];
+        &ALL
     }
 
-    fn new_from_shape(shape: &[(i16, i16)], score: u32) -> Block {
-        let mut rtiles = Vec::new();
-        for &(dx, dy) in shape {
-            rtiles.push((dx, dy, TileType::new_random(score)));
+    fn name(&self) -> &'static str {
+        match *self {
+            Detonation::Auto => "auto",
+            Detonation::Manual => "manual",
         }
-        Block {x:0, y:0, tiles:rtiles}
     }
+}
 
-    fn new_random(score: u32) -> Block {
-        fn shape_1x1() -> &'static [(i16, i16)] {
-            static SHAPE:[(i16, i16); 1] = [(0, 0)];
-            &SHAPE
-        }
-
-        fn shape_8() -> &'static [(i16, i16)] {
-            static SHAPE:[(i16, i16); 2] = [( 0, -1), ( 0,  1)];
-            &SHAPE
-        }
-
-        fn shape_d() -> &'static [(i16, i16)] {
-            static SHAPE:[(i16, i16); 2] = [(-1, -1), ( 0, 0)];
-            &SHAPE
-        }
+// The default keys that KeyBindings start from.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum KeyPreset {
+    Arrows,
+    Wasd,
+}
 
-        fn shape_l() -> &'static [(i16, i16)] {
-            static SHAPE:[(i16, i16); 3] = [(-1, 0), (0,  0), ( 0, -1)];
-            &SHAPE
-        }
+impl Choice for KeyPreset {
+    fn all() -> &'static [KeyPreset] {
+        static ALL: [KeyPreset; 2] = [KeyPreset::Arrows, KeyPreset::Wasd];
+        &ALL
+    }
 
-        fn shape_1x2() -> &'static [(i16, i16)] {
-            static SHAPE:[(i16, i16); 2]
-                = [( 0, -1), ( 0,  0)];
-            &SHAPE
+    fn name(&self) -> &'static str {
+        match *self {
+            KeyPreset::Arrows => "arrows",
+            KeyPreset::Wasd => "wasd",
         }
+    }
+}
 
-        fn shape_1x3() -> &'static [(i16, i16)] {
-            static SHAPE:[(i16, i16); 3]
-                = [( 0, -1), ( 0,  0), (0,  1)];
-            &SHAPE
-        }
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Action {
+    Left,
+    Right,
+    Up,
+    Down,
+    Rotate,
+    RotateBack,
+    FlipH,
+    FlipV,
+    Drop,
+    HardDrop,
+    Swap,
+    Detonate,
+    Undo,
+    Inspect,
+    FastForward,
+    Pause,
+    Quit,
+}
 
-        fn shape_castle() -> &'static [(i16, i16)] {
-            static SHAPE:[(i16, i16); 3] = [( 0, -1), (-1,  0), ( 1,  0)];
-            &SHAPE
-        }
+impl Action {
+    fn all() -> &'static [Action] {
+        static ALL: [Action; 17] = [Action::Left, Action::Right, Action::Up,
+                                    Action::Down, Action::Rotate, Action::RotateBack,
+                                    Action::FlipH, Action::FlipV,
+                                    Action::Drop, Action::HardDrop, Action::Swap,
+                                    Action::Detonate, Action::Undo, Action::Inspect,
+                                    Action::FastForward, Action::Pause, Action::Quit];
+        &ALL
+    }
 
-        return match rand::random::<u8>() % 7 {
-            0 => Block::new_from_shape(shape_1x1(), score),
-            1 => Block::new_from_shape(shape_1x2(), score),
-            2 => Block::new_from_shape(shape_1x3(), score),
-            3 => Block::new_from_shape(shape_8(), score),
-            4 => Block::new_from_shape(shape_d(), score),
-            5 => Block::new_from_shape(shape_l(), score),
-            6 => Block::new_from_shape(shape_castle(), score),
-            _ => unreachable!(),
+    fn name(&self) -> &'static str {
+        match *self {
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Rotate => "rotate",
+            Action::RotateBack => "rotate_back",
+            Action::FlipH => "flip_h",
+            Action::FlipV => "flip_v",
+            Action::Drop => "drop",
+            Action::HardDrop => "hard_drop",
+            Action::Swap => "swap",
+            Action::Detonate => "detonate",
+            Action::Undo => "undo",
+            Action::Inspect => "inspect",
+            Action::FastForward => "fast_forward",
+            Action::Pause => "pause",
+            Action::Quit => "quit",
         }
     }
 
-    fn new_border(w: i16, h: i16) -> Block {
-        assert!(w >= 0);
-        assert!(h >= 0);
-
-        let mut tiles = Vec::new();
-        for x in 0..w-1 {
-            tiles.push((x, 0, TileType::Permanent));
-            tiles.push((x+1, h-1, TileType::Permanent));
-        }
-        for y in 0..h-1 {
-            tiles.push((0, y+1, TileType::Permanent));
-            tiles.push((w-1, y, TileType::Permanent));
+    // The action as seen in a mirror, for the mirrored setting.
+    fn mirrored(&self) -> Action {
+        match *self {
+            Action::Left => Action::Right,
+            Action::Right => Action::Left,
+            Action::Rotate => Action::RotateBack,
+            Action::RotateBack => Action::Rotate,
+            action => action,
         }
-        Block {x:0, y:0, tiles:tiles}
     }
+}
 
-    fn paint_tile(x0: i16, y0: i16, w: i16, h: i16, grid: &mut Grid,
-                  have_up: bool, have_right: bool,
-                  have_down: bool, have_left: bool,
-                  pen1: Pen, pen2: Pen) {
-        let x1 = x0 + w;
-        let y1 = y0 + h;
-        let pen = |b: bool| { if b { pen1 } else { pen2 } };
+// Which keys do what in the game.  The preset chosen in the options
+// can be adjusted in ~/.config/grido/keys, with lines like
+// "rotate = tab e".  Each such line replaces all keys of the action.
+#[derive(Clone, Debug)]
+struct KeyBindings {
+    keys: Vec<(i32, Action)>,
+}
 
-        for &(x, y, len, d, n) in [(x0, y0, w, Direction::Right, have_up),
-                                   (x1, y0, h, Direction::Down, have_right),
-                                   (x0, y1, w, Direction::Right, have_down),
-                                   (x0, y0, h, Direction::Down, have_left)].iter() {
-            grid.paint_wall(x, y, len, d, !n, pen(n));
-        }
+impl KeyBindings {
+    fn preset(preset: KeyPreset) -> KeyBindings {
+        let c = |c: char| c as i32;
+        let keys = match preset {
+            KeyPreset::Arrows => vec![
+                (nc::KEY_LEFT, Action::Left), (nc::KEY_RIGHT, Action::Right),
+                (nc::KEY_UP, Action::Up), (nc::KEY_DOWN, Action::Down),
+                (c('\t'), Action::Rotate), (nc::KEY_BTAB, Action::RotateBack),
+                (c('z'), Action::RotateBack), (c('h'), Action::FlipH),
+                (c('v'), Action::FlipV), (c('\r'), Action::Drop),
+                (c(' '), Action::HardDrop), (nc::KEY_BACKSPACE, Action::Swap), (c('x'), Action::Detonate),
+                (c('u'), Action::Undo), (c('i'), Action::Inspect),
+                (c('f'), Action::FastForward), (c('p'), Action::Pause),
+                (c('q'), Action::Quit)],
+            KeyPreset::Wasd => vec![
+                (c('a'), Action::Left), (c('d'), Action::Right),
+                (c('w'), Action::Up), (c('s'), Action::Down),
+                (c('e'), Action::Rotate), (c('\t'), Action::Rotate),
+                (c('z'), Action::RotateBack), (nc::KEY_BTAB, Action::RotateBack),
+                (c('h'), Action::FlipH), (c('v'), Action::FlipV),
+                (c(' '), Action::HardDrop), (c('\r'), Action::Drop),
+                (c('f'), Action::Swap), (nc::KEY_BACKSPACE, Action::Swap),
+                (c('x'), Action::Detonate), (c('u'), Action::Undo),
+                (c('i'), Action::Inspect), (c('r'), Action::FastForward),
+                (c('p'), Action::Pause), (c('q'), Action::Quit)],
+        };
+        KeyBindings {keys}
     }
 
-    fn paint1(&self, x: i16, y: i16, tt: TileType, grid: &mut Grid) {
-        let up = self.at(x, y-1);
-        let right = self.at(x+1, y);
-        let down = self.at(x, y+1);
-        let left = self.at(x-1, y);
-
-        // A tile is 5x3, but the walls are shared, so we place
-        // them to dx*4, dy*2.
-        let tx = 4 * x;
-        let ty = 2 * y;
-        grid.clear(tx, ty, 5, 3);
+    fn path() -> Option<PathBuf> {
+        user_file("XDG_CONFIG_HOME", ".config", "keys")
+    }
 
-        if tt.is_solid() {
-            fn is_solid_neighbor(n: Option<TileType>) -> bool {
-                if let Some(tt) = n {
-                    tt.is_solid()
-                } else {
-                    false
-                }
+    fn load(preset: KeyPreset) -> KeyBindings {
+        let mut keys = KeyBindings::preset(preset);
+        if let Some(path) = KeyBindings::path() {
+            if let Ok(text) = fs::read_to_string(path) {
+                keys.parse(&text);
             }
-
-            Block::paint_tile(tx, ty, 4, 2, grid,
-                              is_solid_neighbor(up), is_solid_neighbor(right),
-                              is_solid_neighbor(down), is_solid_neighbor(left),
-                              Pen::Thin, Pen::Thik);
-            grid.paint_decoration(tx + 1, ty + 1, tt.render());
-        } else {
-            let c = tt.render();
-            grid.paint_decoration(tx, ty+0, &format!(" {} {} ", c, c));
-            grid.paint_decoration(tx, ty+1, &format!("{} {} {}", c, c, c));
-            grid.paint_decoration(tx, ty+2, &format!(" {} {} ", c, c));
         }
+        keys
     }
 
-    fn paint(&self, grid: &mut Grid) {
-        let &Block {x:x0, y:y0, ref tiles} = self;
+    // The keys that have names: what the keys file calls them, and how
+    // the key bar shows them, with and without Unicode.  Other keys
+    // are printable characters, which stand for themselves.
+    fn names() -> &'static [(&'static str, i32, &'static str, &'static str)] {
+        static NAMES: [(&str, i32, &str, &str); 9] = [
+            ("left", nc::KEY_LEFT, "←", "left"),
+            ("right", nc::KEY_RIGHT, "→", "right"),
+            ("up", nc::KEY_UP, "↑", "up"),
+            ("down", nc::KEY_DOWN, "↓", "down"),
+            ("tab", '\t' as i32, "⇥", "tab"),
+            ("enter", '\r' as i32, "↲", "enter"),
+            ("space", ' ' as i32, "␣", "space"),
+            ("backspace", nc::KEY_BACKSPACE, "⌫", "bksp"),
+            ("shift-tab", nc::KEY_BTAB, "⇤", "s-tab"),
+        ];
+        &NAMES
+    }
 
-        for &(dx, dy, tt) in tiles {
-            if ! tt.is_solid() {
-                self.paint1(x0 + dx, y0 + dy, tt, grid);
+    // Lines that don't make sense are skipped, as in Config::parse.
+    fn parse(&mut self, text: &str) {
+        fn key_code(name: &str) -> Option<i32> {
+            if let Some(&(_, code, _, _)) = KeyBindings::names().iter().find(|k| k.0 == name) {
+                return Some(code);
+            }
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_graphic() => Some(c as i32),
+                _ => None,
             }
         }
 
-        for &(dx, dy, tt) in tiles {
-            if tt.is_solid() {
-                self.paint1(x0 + dx, y0 + dy, tt, grid);
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-        }
-    }
 
-    fn at(&self, x: i16, y: i16) -> Option<TileType> {
-        let &Block {x:x0, y:y0, ref tiles} = self;
-        for &(dx, dy, tt) in tiles {
-            if x == x0+dx && y == y0+dy {
-                return Some(tt)
+            let mut kv = line.splitn(2, '=');
+            let name = kv.next().unwrap_or("").trim();
+            let action = match Action::all().iter().find(|a| a.name() == name) {
+                Some(&action) => action,
+                None => continue,
+            };
+            let codes: Option<Vec<i32>> = match kv.next() {
+                Some(value) => value.split_whitespace().map(key_code).collect(),
+                None => continue,
+            };
+            let codes = match codes {
+                Some(ref codes) if !codes.is_empty() => codes,
+                _ => continue,
+            };
+
+            self.keys.retain(|&(key, a)| a != action && !codes.contains(&key));
+            for &key in codes {
+                self.keys.push((key, action));
             }
         }
-        None
     }
 
-    fn turned(&self) -> Block {
-        let &Block {x:x0, y:y0, ref tiles} = self;
+    fn action(&self, key: i32) -> Option<Action> {
+        self.keys.iter().find(|&&(k, _)| k == key).map(|&(_, action)| action)
+    }
 
-        let mut rtiles = Vec::with_capacity(tiles.len());
-        for &(dx, dy, tt) in tiles {
-            rtiles.push((dy, -dx, tt));
-        }
-        Block {x:x0, y:y0, tiles:rtiles}
+    // Some key that does the action, if any does.
+    fn key(&self, action: Action) -> Option<i32> {
+        self.keys.iter().find(|&&(_, a)| a == action).map(|&(key, _)| key)
     }
 
-    fn moved(&self, dx: i16, dy: i16) -> Block {
-        let &Block {x:x0, y:y0, ref tiles} = self;
-        let mut rtiles = Vec::with_capacity(tiles.len());
-        for &tile in tiles {
-            rtiles.push(tile);
+    // A line with the keys of the most common actions, for the bar
+    // under the playground.  Actions without a key are left out.
+    fn hint(&self, ascii: bool) -> String {
+        // Keys that are neither named nor printable can't be bound
+        // in the keys file, but just in case, they're shown by code.
+        fn key_name(key: i32, ascii: bool) -> String {
+            match KeyBindings::names().iter().find(|k| k.1 == key) {
+                Some(&(_, _, uni, asc)) => (if ascii { asc } else { uni }).to_string(),
+                None => match key {
+                    33..=126 => (key as u8 as char).to_string(),
+                    _ => format!("#{}", key),
+                },
+            }
         }
-        Block {x:x0+dx, y:y0+dy, tiles:rtiles}
-    }
 
-    fn moved_to(&self, x: i16, y: i16) -> Block {
-        self.moved(x - self.x, y - self.y)
+        let name = |action| self.key(action).map(|key| key_name(key, ascii));
+        let moves: Vec<String> = [Action::Left, Action::Right, Action::Up, Action::Down]
+            .iter().filter_map(|&a| name(a)).collect();
+        let mut parts = vec![(moves.join(if ascii { "/" } else { "" }), "move")];
+        for &(action, what) in &[(Action::Rotate, "rotate"), (Action::Drop, "drop"),
+                                 (Action::Swap, "swap"), (Action::Pause, "pause"),
+                                 (Action::Quit, "quit")] {
+            if let Some(key) = name(action) {
+                parts.push((key, what));
+            }
+        }
+        parts.push(("?".to_string(), "hide"));
+
+        parts.iter().filter(|(key, _)| !key.is_empty())
+            .map(|(key, what)| format!("{} {}", key, what))
+            .collect::<Vec<_>>().join("  ")
     }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum ScoreFormat {
+    Raw,
+    Grouped,
+    Short,
+}
 
-    fn collide(blk1: Block, blk2: &Block) -> (Block, Block) {
-        let Block {x:x1, y:y1, tiles:tiles1} = blk1;
-        let mut rtiles1 = Vec::new();
+impl Choice for ScoreFormat {
+    fn all() -> &'static [ScoreFormat] {
+        static ALL: [ScoreFormat; 3] = [ScoreFormat::Raw,
+                                        ScoreFormat::Grouped,
+                                        ScoreFormat::Short];
+        &ALL
+    }
 
-        let &Block {x:x2, y:y2, ..} = blk2;
-        let mut rtiles2 = Vec::new();
-        for &(dx, dy, tt2) in &blk2.tiles {
-            rtiles2.push((dx, dy, tt2));
+    fn name(&self) -> &'static str {
+        match *self {
+            ScoreFormat::Raw => "raw",
+            ScoreFormat::Grouped => "grouped",
+            ScoreFormat::Short => "short",
         }
+    }
+}
 
-        for (dx1, dy1, tt1) in tiles1 {
-            let xx1 = x1 + dx1;
-            let yy1 = y1 + dy1;
-            if let Some(tt2) = blk2.at(xx1, yy1) {
-                if TileType::collides(tt1, tt2) {
-                    let (nt1, nt2) = TileType::collide(tt1, tt2);
-                    if let Some(ntt1) = nt1 {
-                        rtiles1.push((dx1, dy1, ntt1));
-                    }
-                    rtiles2.retain(|&(dx2, dy2, _): &(i16, i16, TileType)|
-                                   x2 + dx2 != xx1 || y2 + dy2 != yy1);
-                    if let Some(ntt2) = nt2 {
-                        rtiles2.push((xx1 - blk2.x, yy1 - blk2.y, ntt2));
+impl ScoreFormat {
+    fn format<T: Into<u64>>(&self, score: T) -> String {
+        let score: u64 = score.into();
+        match *self {
+            ScoreFormat::Raw => format!("{}", score),
+
+            // 1234567 => 1,234,567
+            ScoreFormat::Grouped => {
+                let digits = score.to_string();
+                let mut ret = String::new();
+                for (i, c) in digits.chars().enumerate() {
+                    if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                        ret.push(',');
                     }
+                    ret.push(c);
+                }
+                ret
+            },
+
+            // 1234567 => 1.2M.  Rounded down, so that the score is
+            // never shown higher than it is.
+            ScoreFormat::Short => {
+                let (unit, suffix) = match score {
+                    0..=999 => return format!("{}", score),
+                    1000..=999_999 => (1000, "k"),
+                    _ => (1_000_000, "M"),
+                };
+                let tenths = score / (unit / 10);
+                if tenths < 1000 {
+                    format!("{}.{}{}", tenths / 10, tenths % 10, suffix)
                 } else {
-                    rtiles1.push((dx1, dy1, tt1));
+                    format!("{}{}", score / unit, suffix)
                 }
-            } else {
-                rtiles1.push((dx1, dy1, tt1));
-            }
+            },
         }
+    }
+}
+
+// Settings are kept in ~/.config/grido/config, one "key = value"
+// per line.  Unknown keys and values that don't parse are ignored,
+// so that a bad line doesn't cost the player the rest of their
+// settings.
+#[derive(Clone, PartialEq, Debug)]
+struct Config {
+    popups: PopupStyle,
+    popup_template: String,
+    stalling: StallPenalty,
+    mercy: bool,
+    detonation: Detonation,
+    score_format: ScoreFormat,
+    hints: bool,
+    breakdown: bool,
+    previews: usize,
+    rounded: bool,
+
+    // Tiles in shapes that don't need the colors, see
+    // TileType::render_distinct.
+    distinct: bool,
+    keys: KeyPreset,
+    level_curve: LevelCurve,
+    drop_animation: bool,
+
+    // Nothing on screen moves that doesn't need to: no drifting
+    // particles, no drop animation, gauges a character at a time.
+    // Whatever else comes to move should check this too.
+    reduce_motion: bool,
+
+    // Dashed lines down to where a hard drop would take the block.
+    guide: bool,
+    theme: ThemeName,
+    background: Background,
+    hud: HudLayout,
+
+    // Hide the previews, for players who want the challenge.  The
+    // blocks are still queued up and can be swapped in.
+    blind: bool,
+    game_mode: GameMode,
+
+    // A frame around the playground and the side panel, where the
+    // terminal has room for it.
+    frame: bool,
+
+    // Seconds to place a block at the start of a game, and
+    // milliseconds after a drop during which dropping is ignored.
+    drop_time: usize,
+    drop_grace: usize,
+
+    // Left and right swapped, and rotations turning the other way,
+    // whatever the keys.
+    mirrored: bool,
+
+    // Rows of plain tiles that games start with.
+    handicap: usize,
+    randomizer: Randomizer,
+
+    // Keep the game in progress on disk, see SavedGame::autosave.
+    autosave: bool,
+
+    // Picked in the menu rather than among the options.
+    difficulty: Difficulty,
+
+    // Not a setting as such, but whether the player has discovered
+    // swapping blocks, after which the hint about it goes away.
+    swapped: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {popups: PopupStyle::Plain,
+                popup_template: "[{}]".to_string(),
+                stalling: StallPenalty::Off,
+                mercy: false,
+                detonation: Detonation::Auto,
+                score_format: ScoreFormat::Raw,
+                hints: true,
+                breakdown: false,
+                previews: 3,
+                rounded: false,
+                distinct: false,
+                keys: KeyPreset::Arrows,
+                level_curve: LevelCurve::Quadratic,
+                drop_animation: false,
+                reduce_motion: false,
+                guide: false,
+                theme: ThemeName::Default,
+                background: Background::Dots,
+                hud: HudLayout::Auto,
+                blind: false,
+                game_mode: GameMode::Classic,
+                frame: false,
+                drop_time: (DROP_INTERVAL_MS / 1000) as usize,
+                drop_grace: DROP_GRACE_MS,
+                mirrored: false,
+                handicap: 0,
+                randomizer: Randomizer::Pure,
+                autosave: true,
+                difficulty: Difficulty::Normal,
+                swapped: false}
+    }
+}
 
-        (Block {x:blk1.x, y:blk1.y, tiles:rtiles1},
-         Block {x:blk2.x, y:blk2.y, tiles:rtiles2})
+impl Config {
+    // How to draw things, going by the settings and the command line.
+    fn look(&self, args: &Args) -> Look {
+        Look {outline: if self.rounded { Pen::Round } else { Pen::Thik },
+              ascii: args.ascii, plain: args.plain, distinct: self.distinct}
     }
 
-    fn intersects(&self, blk2: &Block) -> bool {
-        let &Block {x:x1, y:y1, ref tiles} = self;
-        for &(dx1, dy1, _) in tiles {
-            let xx1 = x1 + dx1;
-            let yy1 = y1 + dy1;
-            if let Some(_) = blk2.at(xx1, yy1) {
-                return true;
+    fn path() -> Option<PathBuf> {
+        user_file("XDG_CONFIG_HOME", ".config", "config")
+    }
+
+    fn load() -> Config {
+        let mut config = Config::default();
+        if let Some(path) = Config::path() {
+            if let Ok(text) = fs::read_to_string(path) {
+                config.parse(&text);
             }
         }
-        false
+        config
     }
 
-    fn drop(&self, dest: &mut Block, bd: &Block) -> bool {
-        if self.intersects(bd) || self.intersects(dest) {
-            return false;
+    fn parse(&mut self, text: &str) {
+        fn choice<T: Choice>(dest: &mut T, value: &str) {
+            if let Some(c) = T::from_name(value) {
+                *dest = c;
+            }
         }
 
-        let &Block {x:x1, y:y1, ref tiles} = self;
-        let &mut Block {x:x2, y:y2, tiles:ref mut dtiles} = dest;
-        let ddx = x1 - x2;
-        let ddy = y1 - y2;
-        for &(dx1, dy1, tt1) in tiles {
-            if let Some(ntt) = tt1.drop() {
-                dtiles.push((dx1 + ddx, dy1 + ddy, ntt));
+        fn flag(dest: &mut bool, value: &str) {
+            match value {
+                "on" => *dest = true,
+                "off" => *dest = false,
+                _ => {},
             }
         }
 
+        fn number(dest: &mut usize, value: &str, min: usize, max: usize) {
+            match value.parse() {
+                Ok(n) if n >= min && n <= max => *dest = n,
+                _ => {},
+            }
+        }
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut kv = line.splitn(2, '=');
+            let key = kv.next().unwrap_or("").trim();
+            let value = match kv.next() {
+                Some(value) => value.trim(),
+                None => continue,
+            };
+
+            match key {
+                "popups" => choice(&mut self.popups, value),
+                "popup_template" if value.contains("{}") => {
+                    self.popup_template = value.to_string();
+                },
+                "stalling" => choice(&mut self.stalling, value),
+                "mercy" => flag(&mut self.mercy, value),
+                "detonation" => choice(&mut self.detonation, value),
+                "score_format" => choice(&mut self.score_format, value),
+                "hints" => flag(&mut self.hints, value),
+                "breakdown" => flag(&mut self.breakdown, value),
+                "previews" => number(&mut self.previews, value, 1, MAX_PREVIEWS),
+                "rounded" => flag(&mut self.rounded, value),
+                "distinct" => flag(&mut self.distinct, value),
+                "keys" => choice(&mut self.keys, value),
+                "level_curve" => choice(&mut self.level_curve, value),
+                "drop_animation" => flag(&mut self.drop_animation, value),
+                "reduce_motion" => flag(&mut self.reduce_motion, value),
+                "guide" => flag(&mut self.guide, value),
+                "theme" => choice(&mut self.theme, value),
+                "background" => choice(&mut self.background, value),
+                "hud" => choice(&mut self.hud, value),
+                "blind" => flag(&mut self.blind, value),
+                "game_mode" => choice(&mut self.game_mode, value),
+                "frame" => flag(&mut self.frame, value),
+                "drop_time" => number(&mut self.drop_time, value, MIN_DROP_TIME, MAX_DROP_TIME),
+                "drop_grace" => number(&mut self.drop_grace, value, 0, MAX_DROP_GRACE_MS),
+                "mirrored" => flag(&mut self.mirrored, value),
+                "handicap" => number(&mut self.handicap, value, 0, MAX_HANDICAP),
+                "randomizer" => choice(&mut self.randomizer, value),
+                "autosave" => flag(&mut self.autosave, value),
+                "difficulty" => choice(&mut self.difficulty, value),
+                "swapped" => flag(&mut self.swapped, value),
+                _ => {},
+            }
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        save_file(Config::path(), &self.text())
+    }
+
+    // The settings in the format that parse takes.
+    fn text(&self) -> String {
+        let mut text = String::new();
+        text.push_str(&format!("popups = {}\n", self.popups.name()));
+        text.push_str(&format!("popup_template = {}\n", self.popup_template));
+        text.push_str(&format!("stalling = {}\n", self.stalling.name()));
+        text.push_str(&format!("mercy = {}\n", on_off(self.mercy)));
+        text.push_str(&format!("detonation = {}\n", self.detonation.name()));
+        text.push_str(&format!("score_format = {}\n", self.score_format.name()));
+        text.push_str(&format!("hints = {}\n", on_off(self.hints)));
+        text.push_str(&format!("breakdown = {}\n", on_off(self.breakdown)));
+        text.push_str(&format!("previews = {}\n", self.previews));
+        text.push_str(&format!("rounded = {}\n", on_off(self.rounded)));
+        text.push_str(&format!("distinct = {}\n", on_off(self.distinct)));
+        text.push_str(&format!("keys = {}\n", self.keys.name()));
+        text.push_str(&format!("level_curve = {}\n", self.level_curve.name()));
+        text.push_str(&format!("drop_animation = {}\n", on_off(self.drop_animation)));
+        text.push_str(&format!("reduce_motion = {}\n", on_off(self.reduce_motion)));
+        text.push_str(&format!("guide = {}\n", on_off(self.guide)));
+        text.push_str(&format!("theme = {}\n", self.theme.name()));
+        text.push_str(&format!("background = {}\n", self.background.name()));
+        text.push_str(&format!("hud = {}\n", self.hud.name()));
+        text.push_str(&format!("blind = {}\n", on_off(self.blind)));
+        text.push_str(&format!("game_mode = {}\n", self.game_mode.name()));
+        text.push_str(&format!("frame = {}\n", on_off(self.frame)));
+        text.push_str(&format!("drop_time = {}\n", self.drop_time));
+        text.push_str(&format!("drop_grace = {}\n", self.drop_grace));
+        text.push_str(&format!("mirrored = {}\n", on_off(self.mirrored)));
+        text.push_str(&format!("handicap = {}\n", self.handicap));
+        text.push_str(&format!("randomizer = {}\n", self.randomizer.name()));
+        text.push_str(&format!("autosave = {}\n", on_off(self.autosave)));
+        text.push_str(&format!("difficulty = {}\n", self.difficulty.name()));
+        text.push_str(&format!("swapped = {}\n", on_off(self.swapped)));
+        text
+    }
+
+    // Back to the built-in settings, saved right away, so that a
+    // config that made the game unplayable is gone for good.
+    fn reset(&mut self) -> io::Result<()> {
+        *self = Config::default();
+        self.save()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HighScore {
+    score: u32,
+    name: String,
+    date: String,
+
+    // The GameMode::name of the game.
+    mode: String,
+}
+
+// The best games so far, kept in ~/.local/share/grido/scores, one
+// "score<TAB>name<TAB>date<TAB>mode" per line.  Lines that don't parse
+// are skipped, so a damaged file costs at most the damaged entries.
+// Entries from before game modes have none, they were classic games.
+#[derive(Debug)]
+struct HighScores {
+    entries: Vec<HighScore>,
+}
+
+const HIGH_SCORES: usize = 10;
+
+impl HighScores {
+    fn path() -> Option<PathBuf> {
+        user_file("XDG_DATA_HOME", ".local/share", "scores")
+    }
+
+    fn load() -> HighScores {
+        let text = HighScores::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default();
+        HighScores::parse(&text)
+    }
+
+    fn parse(text: &str) -> HighScores {
+        let mut entries = Vec::new();
+        for line in text.lines() {
+            let mut fields = line.split('\t');
+            let score = match fields.next().and_then(|s| s.trim().parse().ok()) {
+                Some(score) => score,
+                None => continue,
+            };
+            let name = fields.next().unwrap_or("").to_string();
+            let date = fields.next().unwrap_or("").to_string();
+            let mode = fields.next().unwrap_or(GameMode::Classic.name()).to_string();
+            entries.push(HighScore {score, name, date, mode});
+        }
+
+        entries.sort_by_key(|e| cmp::Reverse(e.score));
+        entries.truncate(HIGH_SCORES);
+        HighScores {entries}
+    }
+
+    fn qualifies(&self, score: u32) -> bool {
+        score > 0 && (self.entries.len() < HIGH_SCORES
+                      || self.entries.iter().any(|e| e.score < score))
+    }
+
+    // Put the entry to its place in the table and return its rank,
+    // counted from zero, or None if it didn't make it.
+    fn insert(&mut self, entry: HighScore) -> Option<usize> {
+        if !self.qualifies(entry.score) {
+            return None;
+        }
+
+        let pos = self.entries.iter().position(|e| e.score < entry.score)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(pos, entry);
+        self.entries.truncate(HIGH_SCORES);
+        Some(pos)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut text = String::new();
+        for e in &self.entries {
+            text.push_str(&format!("{}\t{}\t{}\t{}\n", e.score, e.name, e.date, e.mode));
+        }
+        save_file(HighScores::path(), &text)
+    }
+}
+
+// Totals over all the games played to the end, kept in
+// ~/.local/share/grido/lifetime, one "key = value" per line.  As with
+// the high scores, what doesn't parse is skipped.
+#[derive(Default, Debug)]
+struct Lifetime {
+    games: u32,
+    score: u64,
+    exploded: u64,
+    best_level: u8,
+
+    // In seconds, not counting pauses.
+    longest_game: i64,
+}
+
+impl Lifetime {
+    fn path() -> Option<PathBuf> {
+        user_file("XDG_DATA_HOME", ".local/share", "lifetime")
+    }
+
+    fn load() -> Lifetime {
+        let text = Lifetime::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default();
+        Lifetime::parse(&text)
+    }
+
+    fn parse(text: &str) -> Lifetime {
+        fn number<T: str::FromStr>(dest: &mut T, value: &str) {
+            if let Ok(n) = value.parse() {
+                *dest = n;
+            }
+        }
+
+        let mut lifetime = Lifetime::default();
+        for line in text.lines() {
+            let mut kv = line.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => continue,
+            };
+            match key {
+                "games" => number(&mut lifetime.games, value),
+                "score" => number(&mut lifetime.score, value),
+                "exploded" => number(&mut lifetime.exploded, value),
+                "best_level" => number(&mut lifetime.best_level, value),
+                "longest_game" => number(&mut lifetime.longest_game, value),
+                _ => {},
+            }
+        }
+        lifetime
+    }
+
+    fn record(&mut self, score: u32, level: u8, stats: &Stats, played: i64) {
+        self.games += 1;
+        self.score += score as u64;
+        self.exploded += stats.exploded.values().map(|&n| n as u64).sum::<u64>();
+        self.best_level = cmp::max(self.best_level, level);
+        self.longest_game = cmp::max(self.longest_game, played);
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let text = format!("games = {}\nscore = {}\nexploded = {}\n\
+                            best_level = {}\nlongest_game = {}\n",
+                           self.games, self.score, self.exploded,
+                           self.best_level, self.longest_game);
+        save_file(Lifetime::path(), &text)
+    }
+}
+
+// Where the game keeps a file of the given name.  The XDG variable,
+// if set, overrides the directory under $HOME.
+fn user_file(xdg_var: &str, home_dir: &str, name: &str) -> Option<PathBuf> {
+    let dir = match env::var_os(xdg_var) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(home_dir),
+    };
+    Some(dir.join("grido").join(name))
+}
+
+// Write the file through a temporary one, so that a crash halfway
+// through doesn't leave a truncated file behind.
+fn save_file(path: Option<PathBuf>, text: &str) -> io::Result<()> {
+    let path = match path {
+        Some(path) => path,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound,
+                                          "no home directory")),
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(tmp, path)
+}
+
+// How many rotations and swaps are tolerated between two drops
+// before the stall penalty kicks in, and how long before that the
+// player gets a warning.
+const STALL_LIMIT: u32 = 12;
+const STALL_WARNING: u32 = 4;
+
+// How much closer to a drop the Hurry penalty brings the player.
+const STALL_HURRY_MS: i64 = 5000;
+
+// Pick a random free field of the playground to put garbage to.  The
+// fields under the current block and where new blocks appear are
+// not considered.
+fn garbage_field<R: Rng>(pg: &Block, bd: &Block, blk: &Block,
+                         pgw: i16, pgh: i16, rng: &mut R) -> Option<(i16, i16)> {
+    let mut free = Vec::new();
+    for y in 0..pgh {
+        for x in 0..pgw {
+            if pg.at(x, y).is_none() && bd.at(x, y).is_none()
+                && blk.at(x, y).is_none() && (x > 3 || y > 3) {
+                free.push((x, y));
+            }
+        }
+    }
+
+    if free.is_empty() {
+        None
+    } else {
+        Some(free[rng.gen_range(0, free.len())])
+    }
+}
+
+// Where a tile rained down by a meteor lands: it falls down a random
+// column from the top of the playground, and stops on top of the first
+// tile, the border or the current block.  Columns that are full to
+// the top, or where it would land where new blocks appear, are left
+// out.
+fn meteor_field<R: Rng>(pg: &Block, bd: &Block, blk: &Block,
+                        pgw: i16, pgh: i16, rng: &mut R) -> Option<(i16, i16)> {
+    let free = |x, y| pg.at(x, y).is_none() && bd.at(x, y).is_none() && blk.at(x, y).is_none();
+    let mut landings = Vec::new();
+    for x in 0..pgw {
+        if !free(x, 1) {
+            continue;
+        }
+        let mut y = 1;
+        while y + 1 < pgh && free(x, y + 1) {
+            y += 1;
+        }
+        if x > SPAWN_SIZE || y > SPAWN_SIZE {
+            landings.push((x, y));
+        }
+    }
+
+    if landings.is_empty() {
+        None
+    } else {
+        Some(landings[rng.gen_range(0, landings.len())])
+    }
+}
+
+// An empty board, or on hard difficulty, one with a few plain tiles
+// strewn around.  A handicap adds that many rows of plain tiles at the
+// bottom.
+fn starting_board<R: Rng>(difficulty: Difficulty, handicap: usize, bd: &Block, blk: &Block,
+                          pgw: i16, pgh: i16, rng: &mut R) -> Block {
+    let mut pg = Block::new();
+    for _ in 0..difficulty.start_tiles() {
+        if let Some((x, y)) = garbage_field(&pg, bd, blk, pgw, pgh, rng) {
+            pg.tiles.push((x, y, TileType::Plain(0)));
+        }
+    }
+    if handicap > 0 {
+        let fill = Block::random_fill(handicap, &pg, pgw, pgh, rng);
+        pg.tiles.extend(fill.tiles);
+    }
+    pg
+}
+
+// The most rows the handicap setting fills.
+const MAX_HANDICAP: usize = 4;
+
+// When the playground is at least this full, the mercy rule makes
+// the next block all plain.
+const MERCY_OCCUPANCY: f32 = 0.7;
+
+// Which part of the inside of the playground is taken by solid tiles.
+fn occupancy(pg: &Block, pgw: i16, pgh: i16) -> f32 {
+    let solid = pg.tiles.iter().filter(|&&(_, _, tt)| tt.is_solid()).count();
+    solid as f32 / ((pgw - 2) * (pgh - 2)) as f32
+}
+
+// When solid tiles get within this percentage of the playground
+// height of where new blocks appear, the player is warned that the
+// game may be about to end.
+const DANGER_PERCENT: i16 = 20;
+
+// How many fields are free between where new blocks appear and the
+// nearest solid tile, counted in rings around the corner.
+fn spawn_clearance(pg: &Block, pgw: i16, pgh: i16) -> i16 {
+    pg.tiles.iter()
+        .filter(|&&(_, _, tt)| tt.is_solid())
+        .map(|&(x, y, _)| cmp::max(cmp::max(pg.x + x, pg.y + y) - SPAWN_SIZE - 1, 0))
+        .min()
+        .unwrap_or(cmp::max(pgw, pgh))
+}
+
+fn in_danger(pg: &Block, pgw: i16, pgh: i16) -> bool {
+    spawn_clearance(pg, pgw, pgh) <= pgh * DANGER_PERCENT / 100
+}
+
+// Split the points for exploded tiles by tile type.  For each type,
+// returns where the first tile of that type was and the points for
+// all of them.  The points add up to what Block::explode reports.
+fn bonus_breakdown(exploded: &[(i16, i16, TileType)]) -> Vec<(i16, i16, u32)> {
+    let mut ret: Vec<(&str, i16, i16, u32)> = Vec::new();
+    for &(x, y, tt) in exploded {
+        match ret.iter().position(|&(name, _, _, _)| name == tt.name()) {
+            Some(i) => ret[i].3 += tt.bonus(),
+            None => ret.push((tt.name(), x, y, tt.bonus())),
+        }
+    }
+    ret.into_iter().map(|(_, x, y, points)| (x, y, points)).collect()
+}
+
+// How the score of a detonation comes together: the points of the
+// exploded tiles, see Block::explode, times the multiplier, and then
+// the chain bonus on top.
+struct Scoring {
+    base: u32,
+    multiplier: u32,
+    chain_bonus: u32,
+}
+
+impl Scoring {
+    fn new(base: u32, multiplier: u32, chain: u32) -> Scoring {
+        let chain_bonus = if chain > 1 {
+            base * multiplier * (chain - 1) * CHAIN_BONUS_PERCENT / 100
+        } else {
+            0
+        };
+        Scoring {base, multiplier, chain_bonus}
+    }
+
+    fn total(&self) -> u32 {
+        self.base * self.multiplier + self.chain_bonus
+    }
+
+    // The steps from the base points to the total, one per line,
+    // leaving out the ones that don't change anything.
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("{} pts", self.base)];
+        if self.multiplier != 1 {
+            lines.push(format!("x{}", self.multiplier));
+        }
+        if self.chain_bonus > 0 {
+            lines.push(format!("chain +{}", self.chain_bonus));
+        }
+        if lines.len() > 1 {
+            lines.push(format!("= {}", self.total()));
+        }
+        lines
+    }
+}
+
+// How long the drop animation takes, and how many fields away from
+// the block its outline starts.
+const DROP_ANIMATION_MS: i64 = 200;
+const DROP_ANIMATION_SPREAD: i16 = 3;
+
+// Below this many milliseconds left, gauges turn to the last color.
+const GAUGE_LOW_MS: i64 = 3000;
+
+// While the fast forward key is held, the drop gauge runs this many
+// times as fast.  Terminals only send the key again and again, so
+// each press counts as the key held since the one before, up to
+// FAST_HOLD_MS.  Fast forward never takes the gauge below
+// FAST_FLOOR_MS, the last of it runs at the usual speed.
+const FAST_FACTOR: i64 = 4;
+const FAST_HOLD_MS: i64 = 500;
+const FAST_FLOOR_MS: i64 = 500;
+
+// The gauge and the color pair it's in: one while there's plenty of
+// time, another past the half, and a third for the last few seconds.
+// The drop gauge runs from drop_time, less with the levels, and starts
+// out full.  It keeps going during the drop grace, when the player
+// can't drop yet, and a drop that the gauge forces is never held back
+// by it.
+//
+// With reduced motion, the gauge goes a whole character at a time.
+fn paint_gauge(remaining: i64, limit: i64, reduce_motion: bool) -> (String, i16) {
+    // A clock can start the gauge in the future, where it stays full
+    // until the time comes.
+    let remaining = remaining.clamp(0, limit);
+
+    // 96 is 12 * 8: 12 characters times 8 different widths of
+    // unicode block.
+    let frac = (96.0 * (limit as f32 - remaining as f32) / limit as f32) as i32;
+    let frac = if reduce_motion { frac / 8 * 8 } else { frac };
+    let mut timebar = "◂".to_string();
+    for _ in 0 .. (frac / 8) {
+        timebar.push_str("█");
+    }
+    if remaining > 0 {
+        timebar.push_str(match frac % 8 {
+            0 => " ",
+            1 => "▏",
+            2 => "▎",
+            3 => "▍",
+            4 => "▌",
+            5 => "▋",
+            6 => "▊",
+            7 => "▉",
+            _ => "",
+        });
+    }
+    for _ in (frac / 8) .. 11 {
+        timebar.push_str(" ");
+    }
+    timebar.push_str("▸");
+
+    let color = if remaining > limit / 2 {
+        PAIR_GAUGE_PLENTY
+    } else if remaining > GAUGE_LOW_MS {
+        PAIR_GAUGE_HALF
+    } else {
+        PAIR_GAUGE_LOW
+    };
+
+    (timebar, color)
+}
+
+// How many fields a second score popups float up.
+const POPUP_DRIFT: f32 = 0.8;
+
+// Each drop in a chain of scoring drops past the first adds this much
+// to the score of the drop.
+const CHAIN_BONUS_PERCENT: u32 = 25;
+
+// Points for each field that a hard drop moves the block by.
+const HARD_DROP_POINTS: u32 = 2;
+
+// Rotations closer together than this are ignored, so that a held
+// down key doesn't spin the block around.
+const ROTATE_DEBOUNCE_MS: i64 = 150;
+
+// Where F2 in --debug saves the board.
+const DEBUG_SNAPSHOT: &str = "grido-board.json";
+
+// How many keypresses can be waiting for processing.  Anything
+// beyond that is dropped, so that a stuck key can't snowball.
+const MAX_QUEUED_KEYS: usize = 16;
+
+// How many times a second the game looks for input and redraws, unless
+// overridden with --fps.  The drop gauges and the multiplier decay run
+// on the clock, not on frames, so a lower rate doesn't give the player
+// more time.  It only makes the gauges move in coarser steps and keys
+// take a bit longer to show, which is a fair trade on a slow link.
+const FPS: i32 = 50;
+const MIN_FPS: i32 = 1;
+const MAX_FPS: i32 = 100;
+
+const PG_WIDTH: i16 = 16;
+const PG_HEIGHT: i16 = 12;
+
+// Playground sizes that --width and --height accept.  The lower bound
+// leaves room for the spawn area, the upper one keeps grid coordinates
+// well within i16.
+const PG_MIN_SIZE: i16 = 6;
+const PG_MAX_SIZE: i16 = 500;
+
+// How many upcoming blocks play can show.  Their previews are stacked
+// next to the playground, so any more wouldn't fit a usual terminal.
+const MAX_PREVIEWS: usize = 3;
+
+// Columns and lines that play needs to show a playground of the given
+// size together with the previews and the side panel laid out as given.
+// Auto is taken as Side, see HudLayout::resolve for what it turns into.
+fn screen_size(pgw: i16, pgh: i16, layout: HudLayout) -> (i32, i32) {
+    let cols = 4 * pgw as i32 + 1 + 13;
+    let lines = cmp::max(2 * pgh as i32 + 1, 7 * MAX_PREVIEWS as i32);
+    match layout {
+        HudLayout::Below => {
+            let columns = HUD_ROWS.div_ceil(HUD_BELOW_ROWS) as i32;
+            (cmp::max(cols, columns * HUD_COLUMN_WIDTH), lines + HUD_BELOW_ROWS as i32)
+        },
+        HudLayout::Auto | HudLayout::Side => (cols + 14, lines),
+    }
+}
+
+// The size of the terminal, as (cols, lines).
+fn terminal_size() -> (i32, i32) {
+    let (mut maxy, mut maxx) = (0, 0);
+    nc::getmaxyx(unsafe {nc::stdscr}, &mut maxy, &mut maxx);
+    (maxx, maxy)
+}
+
+// Something that happened in a recorded game, with its time in
+// milliseconds since the start of the game.
+#[derive(Debug)]
+enum Event {
+    // A frame of the game and the keys that came during it.
+    Frame(i64, Vec<i32>),
+
+    // A key that the game stopped and waited for, e.g. in a pause.
+    Key(i64, i32),
+}
+
+// A recorded game: the settings and the seed that it was played with,
+// and the events.  The file has the settings in the config file
+// format, then a line with "---", then one event per line.
+struct Recording {
+    config: Config,
+    seed: u64,
+    width: i16,
+    height: i16,
+    events: VecDeque<Event>,
+}
+
+impl Recording {
+    fn load(path: &Path) -> Result<Recording, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        let bad = |n: usize| format!("{}:{}: malformed recording", path.display(), n + 1);
+
+        let mut rec = Recording {config: Config::default(), seed: 0,
+                                 width: PG_WIDTH, height: PG_HEIGHT,
+                                 events: VecDeque::new()};
+        let mut seed = None;
+        let mut lines = text.lines().enumerate();
+        let mut header = String::new();
+        for (n, line) in &mut lines {
+            if line == "---" {
+                break;
+            }
+
+            let mut kv = line.splitn(2, '=').map(|s| s.trim());
+            match (kv.next(), kv.next()) {
+                (Some("seed"), Some(v)) => seed = Some(v.parse().map_err(|_| bad(n))?),
+                (Some("width"), Some(v)) => rec.width = v.parse().map_err(|_| bad(n))?,
+                (Some("height"), Some(v)) => rec.height = v.parse().map_err(|_| bad(n))?,
+                _ => {
+                    header.push_str(line);
+                    header.push('\n');
+                },
+            }
+        }
+        rec.config.parse(&header);
+        rec.seed = seed.ok_or(format!("{}: recording has no seed", path.display()))?;
+
+        for (n, line) in lines {
+            let mut words = line.split_whitespace();
+            let kind = words.next();
+            let ms = words.next().and_then(|w| w.parse().ok()).ok_or(bad(n))?;
+            let keys = words.map(|w| w.parse().map_err(|_| bad(n)))
+                .collect::<Result<Vec<i32>, String>>()?;
+            rec.events.push_back(match (kind, keys.len()) {
+                (Some("frame"), _) => Event::Frame(ms, keys),
+                (Some("key"), 1) => Event::Key(ms, keys[0]),
+                _ => return Err(bad(n)),
+            });
+        }
+
+        Ok(rec)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = format!("seed = {}\nwidth = {}\nheight = {}\n",
+                               self.seed, self.width, self.height);
+        text.push_str(&self.config.text());
+        text.push_str("---\n");
+        for event in &self.events {
+            match *event {
+                Event::Frame(ms, ref keys) => {
+                    text.push_str(&format!("frame {}", ms));
+                    for key in keys {
+                        text.push_str(&format!(" {}", key));
+                    }
+                    text.push('\n');
+                },
+                Event::Key(ms, key) => text.push_str(&format!("key {} {}\n", ms, key)),
+            }
+        }
+        save_file(Some(path.to_path_buf()), &text)
+    }
+}
+
+// A game that the player quit in the middle of, to be continued from
+// the menu.  The file has a "key = value" line for each of the
+// numbers, and a line for each block, see Block::to_snapshot.  The
+// gauges are kept as the time they had left.
+#[derive(Debug)]
+struct SavedGame {
+    width: i16,
+    height: i16,
+    pg: Block,
+    blk: Block,
+    queue: VecDeque<Block>,
+    mercy: bool,
+    score: u32,
+    multiplier: u32,
+    drop_left: i64,
+    mult_left: i64,
+}
+
+impl SavedGame {
+    fn path() -> Option<PathBuf> {
+        user_file("XDG_DATA_HOME", ".local/share", "saved")
+    }
+
+    fn exists() -> bool {
+        SavedGame::path().is_some_and(|path| path.exists())
+    }
+
+    fn load() -> Result<SavedGame, String> {
+        let path = SavedGame::path().ok_or("no home directory")?;
+        let text = fs::read_to_string(&path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        SavedGame::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    fn parse(text: &str) -> Result<SavedGame, String> {
+        let mut saved = SavedGame {width: PG_WIDTH, height: PG_HEIGHT,
+                                   pg: Block::new(), blk: Block::new(),
+                                   queue: VecDeque::new(), mercy: false,
+                                   score: 0, multiplier: 1,
+                                   drop_left: 0, mult_left: 0};
+        for (n, line) in text.lines().enumerate() {
+            let bad = || format!("line {}: malformed saved game", n + 1);
+            let mut kv = line.splitn(2, '=').map(|s| s.trim());
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Err(bad()),
+            };
+            let num = |v: &str| v.parse::<i64>().map_err(|_| bad());
+            match key {
+                "width" => saved.width = num(value)? as i16,
+                "height" => saved.height = num(value)? as i16,
+                "score" => saved.score = num(value)? as u32,
+                "multiplier" => saved.multiplier = num(value)? as u32,
+                "drop_left" => saved.drop_left = num(value)?,
+                "mult_left" => saved.mult_left = num(value)?,
+                "mercy" => saved.mercy = value == "on",
+                "pg" => saved.pg = Block::from_snapshot(value).map_err(|_| bad())?,
+                "block" => saved.blk = Block::from_snapshot(value).map_err(|_| bad())?,
+                "next" => saved.queue.push_back(Block::from_snapshot(value)
+                                                .map_err(|_| bad())?),
+                _ => return Err(bad()),
+            }
+        }
+
+        if saved.blk.tiles.is_empty() || saved.queue.is_empty() {
+            return Err("incomplete saved game".to_string());
+        }
+        Ok(saved)
+    }
+
+    // The game in the format that parse takes.
+    fn text(&self) -> String {
+        let mut text = format!("width = {}\nheight = {}\n", self.width, self.height);
+        text.push_str(&format!("score = {}\n", self.score));
+        text.push_str(&format!("multiplier = {}\n", self.multiplier));
+        text.push_str(&format!("drop_left = {}\n", self.drop_left));
+        text.push_str(&format!("mult_left = {}\n", self.mult_left));
+        text.push_str(&format!("mercy = {}\n", on_off(self.mercy)));
+        text.push_str(&format!("pg = {}\n", self.pg.to_snapshot()));
+        text.push_str(&format!("block = {}\n", self.blk.to_snapshot()));
+        for next in &self.queue {
+            text.push_str(&format!("next = {}\n", next.to_snapshot()));
+        }
+        text
+    }
+
+    fn save(&self) -> io::Result<()> {
+        save_file(SavedGame::path(), &self.text())
+    }
+
+    fn remove() {
+        if let Some(path) = SavedGame::path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    // With the autosave setting, games in progress are also kept here
+    // after every drop, and the file goes away when the game ends.
+    // One that's still around after that was cut short.
+    fn autosave_path() -> Option<PathBuf> {
+        user_file("XDG_DATA_HOME", ".local/share", "autosave")
+    }
+
+    fn autosave(&self) -> io::Result<()> {
+        save_file(SavedGame::autosave_path(), &self.text())
+    }
+
+    fn remove_autosave() {
+        if let Some(path) = SavedGame::autosave_path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    // Whether there's an autosave that's newer than the saved game,
+    // if there's one.
+    fn recoverable() -> bool {
+        let modified = |path: Option<PathBuf>| {
+            path.and_then(|path| fs::metadata(path).ok())
+                .and_then(|meta| meta.modified().ok())
+        };
+        match (modified(SavedGame::autosave_path()), modified(SavedGame::path())) {
+            (Some(auto), Some(saved)) => auto > saved,
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+
+    // Make the autosave the saved game, for continue_game to pick up.
+    fn recover() -> io::Result<()> {
+        match (SavedGame::autosave_path(), SavedGame::path()) {
+            (Some(auto), Some(saved)) => fs::rename(auto, saved),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "no home directory")),
+        }
+    }
+}
+
+// Where play takes the keys and the time from.  That's the keyboard
+// and the clock, which may be recorded on the way, or a recording
+// being replayed.
+//
+// The time only moves at the start of each frame and when a key is
+// waited for, so that replaying the same frames at the same times
+// gives the same game.  Frames where no key came and nothing was
+// kept don't change the game, and aren't recorded.
+struct Feed {
+    start: time::SteadyTime,
+    now: time::SteadyTime,
+
+    // When a replay would have started had it gone at the pace of the
+    // recording all along.
+    pace: time::SteadyTime,
+
+    replay: Option<VecDeque<Event>>,
+    record: Option<Vec<Event>>,
+
+    // Keys of the current frame, and whether to record it even if
+    // there are none.
+    keys: Vec<i32>,
+    keep: bool,
+
+    // A demo plays by itself until any key comes.
+    demo: bool,
+    interrupted: bool,
+}
+
+// Longest pause between frames of a replay.  Whatever the player spent
+// longer on isn't worth watching.
+const REPLAY_MAX_WAIT_MS: i64 = 1000;
+
+impl Feed {
+    fn live(record: bool) -> Feed {
+        let now = time::SteadyTime::now();
+        Feed {start: now, now, pace: now, replay: None,
+              record: if record { Some(Vec::new()) } else { None },
+              keys: Vec::new(), keep: false, demo: false, interrupted: false}
+    }
+
+    fn replay(events: VecDeque<Event>) -> Feed {
+        let now = time::SteadyTime::now();
+        Feed {start: now, now, pace: now, replay: Some(events), record: None,
+              keys: Vec::new(), keep: false, demo: false, interrupted: false}
+    }
+
+    fn demo() -> Feed {
+        Feed {demo: true, ..Feed::live(false)}
+    }
+
+    fn replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    fn demo_running(&self) -> bool {
+        self.demo
+    }
+
+    fn now(&self) -> time::SteadyTime {
+        self.now
+    }
+
+    fn ms(&self) -> i64 {
+        (self.now - self.start).num_milliseconds()
+    }
+
+    // Record the current frame even if no keys came during it.
+    fn keep(&mut self) {
+        self.keep = true;
+    }
+
+    fn end_frame(&mut self) {
+        let keys = mem::take(&mut self.keys);
+        let ms = self.ms();
+        if let Some(ref mut record) = self.record {
+            if self.keep || !keys.is_empty() {
+                record.push(Event::Frame(ms, keys));
+            }
+        }
+        self.keep = false;
+    }
+
+    // Start a new frame.  Returns false if there's nothing more to
+    // replay, or the viewer stopped the replay.
+    fn frame(&mut self) -> bool {
+        self.end_frame();
+        if self.interrupted {
+            return false;
+        }
+
+        let (ms, keys) = match self.replay {
+            None => {
+                self.now = time::SteadyTime::now();
+                return true;
+            },
+            Some(ref mut events) => match events.pop_front() {
+                Some(Event::Frame(ms, keys)) => (ms, keys),
+                _ => return false,
+            },
+        };
+
+        let elapsed = (time::SteadyTime::now() - self.pace).num_milliseconds();
+        let wait = (ms - elapsed).clamp(0, REPLAY_MAX_WAIT_MS);
+        nc::timeout(wait as i32);
+        if nc::getch() == 'q' as i32 {
+            return false;
+        }
+
+        // Waits that were cut short would have the replay hurry to
+        // catch up afterwards.
+        self.pace = time::SteadyTime::now() - time::Duration::milliseconds(ms);
+        self.now = self.start + time::Duration::milliseconds(ms);
+        self.keys = keys;
         true
     }
 
-    fn collides_with(&self, with: &Block) -> bool {
-        let &Block {x:x0, y:y0, ref tiles} = self;
-        for &(dx, dy, tt1) in tiles {
-            if let Some(tt2) = with.at(x0+dx, y0+dy) {
-                if TileType::collides(tt1, tt2) {
-                    return true;
+    // Keys that came during the current frame.  Unless some are there
+    // already, wait for them up to the given time.
+    fn keys(&mut self, wait_ms: i32) -> Vec<i32> {
+        if self.replaying() {
+            return mem::take(&mut self.keys);
+        }
+
+        let mut keys = Vec::new();
+        nc::timeout(wait_ms);
+        let mut ch = nc::getch();
+        nc::timeout(0);
+        while ch != nc::ERR {
+            keys.push(ch);
+            ch = nc::getch();
+        }
+
+        if self.demo && !keys.is_empty() {
+            self.interrupted = true;
+            return Vec::new();
+        }
+
+        self.keys.extend(&keys);
+        keys
+    }
+
+    // Wait for a key.  The time moves to when it came.
+    fn key(&mut self) -> i32 {
+        self.end_frame();
+
+        if let Some(ref mut events) = self.replay {
+            return match events.pop_front() {
+                Some(Event::Key(ms, key)) => {
+                    self.now = self.start + time::Duration::milliseconds(ms);
+                    key
+                },
+                Some(event) => {
+                    events.push_front(event);
+                    nc::ERR
+                },
+                None => nc::ERR,
+            };
+        }
+
+        nc::timeout(-1);
+        let key = nc::getch();
+        self.now = time::SteadyTime::now();
+        let ms = self.ms();
+        if let Some(ref mut record) = self.record {
+            record.push(Event::Key(ms, key));
+        }
+        key
+    }
+
+    // Whether the replay has keys waiting outside of frames, i.e. the
+    // recorded player sat in a prompt at this point.
+    fn key_pending(&self) -> bool {
+        match self.replay {
+            Some(ref events) => matches!(events.front(), Some(&Event::Key(..))),
+            None => false,
+        }
+    }
+
+    // The recorded events, if the game was recorded.
+    fn recorded(mut self) -> Option<VecDeque<Event>> {
+        self.end_frame();
+        self.record.take().map(|record| record.into_iter().collect())
+    }
+}
+
+// How long the player has to place a block before it drops by itself.
+// It starts at the drop_time setting, by default DROP_INTERVAL_MS, and
+// gets shorter with each level, down to a floor.
+const DROP_INTERVAL_MS: i64 = 15000;
+const DROP_INTERVAL_STEP_MS: i64 = 800;
+const DROP_INTERVAL_MIN_MS: i64 = 4000;
+
+// The range of the drop_time setting, in seconds.
+const MIN_DROP_TIME: usize = 5;
+const MAX_DROP_TIME: usize = 30;
+
+// For a while after a drop, dropping again is ignored, so that a
+// double press doesn't drop the next block right where it appears.
+// This is the default of the drop_grace setting, in milliseconds.
+const DROP_GRACE_MS: usize = 500;
+const MAX_DROP_GRACE_MS: usize = 1000;
+
+// How long the multiplier holds before it decays by one.
+const MULT_INTERVAL_MS: i64 = 60000;
+
+fn drop_interval(level: u8, start_ms: i64) -> i64 {
+    cmp::max(start_ms - level as i64 * DROP_INTERVAL_STEP_MS,
+             DROP_INTERVAL_MIN_MS)
+}
+
+// What happened during a game, for the game-over screen.
+#[derive(Clone, Default, Debug)]
+struct Stats {
+    // Exploded tiles by TileType::name.
+    exploded: BTreeMap<&'static str, u32>,
+    best_bonus: u32,
+    rotations: u32,
+
+    // Moves, turns, swaps and drops that the player asked for.
+    moves: u32,
+
+    // Drops in a row made with the multiplier above 1.
+    streak: u32,
+    longest_streak: u32,
+}
+
+impl Stats {
+    fn detonated(&mut self, exploded: &[(i16, i16, TileType)], bonus: u32) {
+        for &(_, _, tt) in exploded {
+            *self.exploded.entry(tt.name()).or_insert(0) += 1;
+        }
+        self.best_bonus = cmp::max(self.best_bonus, bonus);
+    }
+
+    fn dropped(&mut self, multiplier: u32) {
+        self.streak = if multiplier > 1 { self.streak + 1 } else { 0 };
+        self.longest_streak = cmp::max(self.longest_streak, self.streak);
+    }
+}
+
+// The multiplier never goes below 1.  At 0, explosions would score
+// nothing, and the decay only ever moves the multiplier towards 1.
+const MIN_MULTIPLIER: u32 = 1;
+
+fn apply_dmult(multiplier: u32, dmult: i32) -> u32 {
+    if dmult < 0 {
+        cmp::max(multiplier.saturating_sub(-dmult as u32), MIN_MULTIPLIER)
+    } else {
+        multiplier + dmult as u32
+    }
+}
+
+// How often the demo presses a key, and how many it presses at most
+// to get a block in place.
+const DEMO_STEP_MS: i64 = 150;
+const DEMO_MAX_STEPS: u32 = 40;
+
+// The next key press that gets blk to where the AI wants it: turn it
+// until it's the same shape, then move it over and drop it.
+fn demo_action(blk: &Block, target: &Block) -> Action {
+    let same_shape = blk.tiles.len() == target.tiles.len()
+        && target.tiles.iter().all(|t| blk.tiles.contains(t));
+    if !same_shape {
+        Action::Rotate
+    } else if blk.x < target.x {
+        Action::Right
+    } else if blk.x > target.x {
+        Action::Left
+    } else if blk.y < target.y {
+        Action::Down
+    } else if blk.y > target.y {
+        Action::Up
+    } else {
+        Action::Drop
+    }
+}
+
+// Find where to drop blk to score the most.  Returns blk turned and
+// moved to that place, or None if it can't be dropped anywhere.  The
+// AI doesn't care whether the place is actually reachable, the block
+// is simply teleported there.
+fn suggest_move(blk: &Block, pg: &Block, bd: &Block, pgw: i16, pgh: i16) -> Option<Block> {
+    // What's in each field: None if it's free, Some(true) if it holds
+    // a tile that can be part of a plain 3x3, and Some(false) if it's
+    // taken by something else.  Spills are taken, because Block::drop
+    // refuses to drop onto them.
+    let mut fields: Vec<Option<bool>> = vec![None; (pgw * pgh) as usize];
+    for b in &[pg, bd] {
+        for &(dx, dy, tt) in &b.tiles {
+            let (x, y) = (b.x + dx, b.y + dy);
+            if x >= 0 && x < pgw && y >= 0 && y < pgh {
+                fields[(y * pgw + x) as usize] = Some(tt.is_plain());
+            }
+        }
+    }
+    let field = |x: i16, y: i16| {
+        if x < 0 || x >= pgw || y < 0 || y >= pgh {
+            Some(false)
+        } else {
+            fields[(y * pgw + x) as usize]
+        }
+    };
+
+    let mut best: Option<(i32, Block)> = None;
+    let mut turned = blk.clone();
+    for _ in 0..4 {
+        turned = turned.turned();
+        for y in 0..pgh {
+            for x in 0..pgw {
+                let cand = turned.moved_to(x, y);
+                let cells: Vec<(i16, i16, bool)> = cand.tiles.iter()
+                    .map(|&(dx, dy, tt)| (x + dx, y + dy,
+                                          tt.drop().is_some_and(|tt| tt.is_plain())))
+                    .collect();
+                if cells.iter().any(|&(xx, yy, _)| field(xx, yy).is_some()) {
+                    continue;
+                }
+
+                let at = |xx: i16, yy: i16| {
+                    match cells.iter().find(|&&(cx, cy, _)| cx == xx && cy == yy) {
+                        Some(&(_, _, plain)) => Some(plain),
+                        None => field(xx, yy),
+                    }
+                };
+
+                // Look at all 3x3 areas that the block touches.  A
+                // drop can only explode something if it completes
+                // one of them, and only then is it worth going
+                // through the real thing.  Otherwise prefer places
+                // that get plain areas closer to completion.
+                let mut completes = false;
+                let mut value = 0;
+                for &(xx, yy, _) in &cells {
+                    for ox in xx - 2..xx + 1 {
+                        for oy in yy - 2..yy + 1 {
+                            let mut area = [None; 9];
+                            for (i, f) in area.iter_mut().enumerate() {
+                                *f = at(ox + i as i16 % 3, oy + i as i16 / 3);
+                            }
+                            if area.iter().all(|f| f.is_some()) {
+                                completes = true;
+                            }
+                            if area.iter().all(|&f| f != Some(false)) {
+                                let n = area.iter().filter(|f| f.is_some()).count() as i32;
+                                value += n * n;
+                            }
+                        }
+                    }
+
+                    // Stay away from where new blocks appear.
+                    if xx <= 3 && yy <= 3 {
+                        value -= 100;
+                    }
+                }
+
+                if completes {
+                    if let Some(outcome) = simulate_drop(pg, &cand, bd) {
+                        value += 1000 * outcome.hits as i32;
+                    }
+                }
+
+                if best.as_ref().is_none_or(|&(v, _)| value > v) {
+                    best = Some((value, cand));
+                }
+            }
+        }
+    }
+
+    best.map(|(_, blk)| blk)
+}
+
+// The AI games are cut short after this many drops, in case it finds
+// a way to go on forever.
+const BENCH_MAX_DROPS: u32 = 5000;
+
+struct BenchGame {
+    score: u32,
+    drops: u32,
+    exploded: Vec<TileType>,
+}
+
+// Let the AI play one game, with blocks generated from the given
+// seed.  There are no timers, so the multiplier never decays.
+fn bench_game(seed: usize) -> BenchGame {
+    let mut rng: StdRng = SeedableRng::from_seed(&[seed][..]);
+    let bd = Block::new_border(PG_WIDTH, PG_HEIGHT);
+    let mut pg = Block::new();
+    let mut score = 0;
+    let mut multiplier: u32 = 1;
+    let mut drops = 0;
+    let mut exploded_types = Vec::new();
+
+    let curve = LevelCurve::Quadratic;
+    let mut blk = Block::new_random(curve.level(score), &mut rng).moved_to(2, 2);
+    while drops < BENCH_MAX_DROPS {
+        let outcome = match suggest_move(&blk, &pg, &bd, PG_WIDTH, PG_HEIGHT)
+            .and_then(|placed| simulate_drop(&pg, &placed, &bd)) {
+            Some(outcome) => outcome,
+            None => break,
+        };
+        drops += 1;
+
+        pg = outcome.pg;
+        score += outcome.hits * multiplier;
+        multiplier = apply_dmult(multiplier, outcome.dmult);
+        for (_, _, tt) in outcome.exploded {
+            exploded_types.push(tt);
+        }
+
+        blk = Block::new_random(curve.level(score), &mut rng).moved_to(2, 2);
+        if block_collides(&blk, &bd, &pg) {
+            break;
+        }
+    }
+
+    BenchGame {score, drops, exploded: exploded_types}
+}
+
+// Explode the board in the map file or snapshot and print what
+// happened, so that the engine can be poked at without a terminal.
+fn simulate(path: &str) -> Result<(), String> {
+    let map = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let (mut pg, w, h) = if map.trim_start().starts_with('{') {
+        // A snapshot, as dumped with F2 in --debug.
+        let pg = Block::from_snapshot(&map).map_err(|err| format!("{}: {}", path, err))?;
+        let (_, _, x2, y2) = pg.bounds().unwrap_or((0, 0, -1, -1));
+        (pg, x2 + 1, y2 + 1)
+    } else {
+        let pg = Block::from_map(&map).map_err(|err| format!("{}: {}", path, err))?;
+        let w = map.lines().map(|line| line.chars().count()).max().unwrap_or(0) as i16;
+        let h = map.lines().count() as i16;
+        (pg, w, h)
+    };
+
+    let xp = pg.explode();
+    println!("Hits:        {}", xp.hits);
+    println!("Multiplier:  {:+}", xp.dmult);
+    println!("Extra time:  {} ms", xp.time);
+    println!("Slowdown:    {}", if xp.slowdown { "yes" } else { "no" });
+    println!("Meteors:     {}", xp.meteors);
+    println!("Exploded:    {}", xp.exploded.len());
+    for (x, y, tt) in xp.exploded {
+        println!("  {:>3} {:>3}  {}", x, y, tt.name());
+    }
+    println!();
+    print!("{}", pg.to_map(w, h));
+    Ok(())
+}
+
+fn ai_bench(games: u32) {
+    let mut total_score = 0u64;
+    let mut best_score = 0;
+    let mut total_level = 0u64;
+    let mut total_drops = 0u64;
+    let mut exploded: BTreeMap<&'static str, u64> = BTreeMap::new();
+
+    for seed in 0..games {
+        let game = bench_game(seed as usize);
+        total_score += game.score as u64;
+        best_score = cmp::max(best_score, game.score);
+        total_level += LevelCurve::Quadratic.level(game.score) as u64;
+        total_drops += game.drops as u64;
+        for tt in game.exploded {
+            *exploded.entry(tt.name()).or_insert(0) += 1;
+        }
+    }
+
+    let n = cmp::max(games, 1) as f64;
+    println!("Games:          {}", games);
+    println!("Average score:  {:.1}", total_score as f64 / n);
+    println!("Best score:     {}", best_score);
+    println!("Average level:  {:.2}", total_level as f64 / n);
+    println!("Average drops:  {:.1}", total_drops as f64 / n);
+    println!();
+
+    let mut exploded: Vec<_> = exploded.into_iter().collect();
+    exploded.sort_by_key(|e| cmp::Reverse(e.1));
+    println!("{:<16}{:>10}{:>12}", "Exploded", "total", "per game");
+    for (name, count) in exploded {
+        println!("{:<16}{:>10}{:>12.2}", name, count, count as f64 / n);
+    }
+}
+
+const TILE_STATS_DRAWS: u32 = 10000;
+
+// Print how often TileType::new_random deals each tile at the given
+// score, to check the odds and which tiles each level brings in.
+fn tile_stats(score: u32, curve: LevelCurve, seed: Option<u64>) {
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut rng: StdRng = SeedableRng::from_seed(&[seed as usize][..]);
+    let lvl = curve.level(score);
+
+    let mut counts: BTreeMap<String, u32> = BTreeMap::new();
+    for _ in 0..TILE_STATS_DRAWS {
+        *counts.entry(format!("{:?}", TileType::new_random(lvl, &mut rng))).or_insert(0) += 1;
+    }
+
+    println!("Score:  {}", score);
+    println!("Level:  {} ({})", lvl, curve.name());
+    println!("Draws:  {}", TILE_STATS_DRAWS);
+    println!();
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by_key(|c| cmp::Reverse(c.1));
+    for (name, count) in counts {
+        let share = 100. * count as f64 / TILE_STATS_DRAWS as f64;
+        println!("{:<20}{:>7}{:>8.2}%  {}", name, count, share,
+                 "#".repeat((share / 2.).ceil() as usize));
+    }
+}
+
+// Things in the game worth a sound.
+#[derive(Copy, Clone, Debug)]
+enum Cue {
+    Explosion,
+    Multiplier,
+    Danger,
+    GameOver,
+}
+
+// The terminal bell, unless it's muted, which it is without --sound.
+// Both beep and flash only send a control sequence, they don't wait
+// for anything.
+#[derive(Copy, Clone, Debug)]
+struct Bell {
+    muted: bool,
+}
+
+impl Bell {
+    fn ring(&self, cue: Cue) {
+        if self.muted {
+            return;
+        }
+        match cue {
+            Cue::Explosion => nc::beep(),
+            Cue::Multiplier | Cue::Danger => nc::flash(),
+            Cue::GameOver => {
+                nc::beep();
+                nc::flash()
+            },
+        };
+    }
+}
+
+// The game as it was right before the last drop: whatever a drop and
+// the explosion that follows can change.
+struct Undo {
+    pg: Block,
+    blk: Block,
+    queue: VecDeque<Block>,
+    mercy: bool,
+    score: u32,
+    multiplier: u32,
+    chain: u32,
+    stats: Stats,
+    last_mult_time: time::SteadyTime,
+    slow_until: Option<time::SteadyTime>,
+    meteors: VecDeque<time::SteadyTime>,
+}
+
+// Where play takes the blocks from.
+trait BlockSource {
+    fn next(&mut self, lvl: u8, rng: &mut StdRng) -> Block;
+
+    // Start over, for a restarted game.
+    fn rewind(&mut self) {}
+}
+
+// Blocks drawn at random, as fits the level.
+struct Random;
+
+impl BlockSource for Random {
+    fn next(&mut self, lvl: u8, rng: &mut StdRng) -> Block {
+        Block::new_random(lvl, rng)
+    }
+}
+
+// Like Random, but the shapes are dealt from a shuffled bag that has
+// each of them once, so that none stays away for long.  The tiles are
+// as random as ever.
+struct Bag {
+    // The shapes still in the bag, see Block::shape.
+    shapes: Vec<usize>,
+}
+
+impl Bag {
+    fn new() -> Bag {
+        Bag {shapes: Vec::new()}
+    }
+}
+
+impl BlockSource for Bag {
+    fn next(&mut self, lvl: u8, rng: &mut StdRng) -> Block {
+        if self.shapes.is_empty() {
+            self.shapes = (0..SHAPES).collect();
+            rng.shuffle(&mut self.shapes);
+        }
+        let n = self.shapes.pop().unwrap();
+        Block::new_random_of(n, lvl, rng)
+    }
+
+    fn rewind(&mut self) {
+        self.shapes.clear();
+    }
+}
+
+// The blocks of a practice file, over and over.  The file has the
+// blocks as maps for Block::from_map, separated by empty lines.
+#[derive(Clone, Debug)]
+struct Scripted {
+    blocks: Vec<Block>,
+    pos: usize,
+}
+
+impl Scripted {
+    fn load(path: &Path) -> Result<Scripted, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        let mut blocks = Vec::new();
+        let mut map = String::new();
+        for line in text.lines().chain(Some("")) {
+            if !line.trim().is_empty() {
+                map.push_str(line);
+                map.push('\n');
+                continue;
+            }
+            if map.is_empty() {
+                continue;
+            }
+
+            let blk = Block::from_map(&map)
+                .map_err(|err| format!("{}: block {}: {}", path.display(),
+                                       blocks.len() + 1, err))?;
+            map.clear();
+
+            // Whatever the map has around the block doesn't count.
+            let (x1, y1, _, _) = blk.bounds()
+                .ok_or(format!("{}: block {} is empty", path.display(), blocks.len() + 1))?;
+            blocks.push(Block {x: 0, y: 0,
+                               tiles: blk.tiles.iter()
+                                   .map(|&(x, y, tt)| (x - x1, y - y1, tt)).collect()});
+        }
+
+        if blocks.is_empty() {
+            return Err(format!("{}: no blocks", path.display()));
+        }
+        Ok(Scripted {blocks, pos: 0})
+    }
+}
+
+impl BlockSource for Scripted {
+    fn next(&mut self, _lvl: u8, _rng: &mut StdRng) -> Block {
+        let blk = self.blocks[self.pos].clone();
+        self.pos = (self.pos + 1) % self.blocks.len();
+        blk
+    }
+
+    fn rewind(&mut self) {
+        self.pos = 0;
+    }
+}
+
+// Where play gets its keys from.  A replay has the events of a
+// recorded game to play instead of reading the keyboard.  A continued
+// game is played live, starting from where it was saved, and so is
+// practice, only with the blocks from a file.
+enum Mode {
+    Live,
+    Continue(SavedGame),
+    Practice(Scripted),
+    Replay(VecDeque<Event>),
+    Demo,
+}
+
+// What to do once play is done.
+#[derive(Copy, Clone, PartialEq)]
+enum AfterGame {
+    Menu,
+
+    // Start a new game with the same settings, without the menu.
+    Restart,
+}
+
+// How a game came to an end.
+enum Ending {
+    // The player quit at the given time, from the game or the pause
+    // menu.
+    Quit(time::SteadyTime),
+
+    // The game stopped before it was over or quit, like when the
+    // replay ran out or the terminal got too small.
+    Stopped,
+
+    // The next block didn't fit.
+    Over,
+}
+
+// What the keys of a frame left for step_simulation to do.
+#[derive(Default)]
+struct Input {
+    drop: bool,
+
+    // How far a hard drop moved the block, to be paid if the drop
+    // goes through.
+    hard_drop: u32,
+
+    // Tiles moved into spills, see Block::collide.
+    splashes: Vec<(i16, i16, LiquidType)>,
+}
+
+// What play needs for taking keys and showing the game, apart from
+// the game itself.
+struct Controls {
+    keys: KeyBindings,
+    look: Look,
+    frame_ms: i32,
+    debug: bool,
+    input: VecDeque<i32>,
+
+    // Where the demo is taking the current block, and how many steps
+    // it took so far.
+    demo_target: Option<Block>,
+    demo_steps: u32,
+    demo_time: time::SteadyTime,
+
+    // The bar with the keys under the playground, toggled with ?.  It
+    // comes and goes with the other hints.
+    key_hint: String,
+    show_keys: bool,
+
+    // With --debug, F1 shows how long frames take, measured on the
+    // wall clock even in replays.
+    overlay: bool,
+    frame_time: time::Duration,
+
+    // With --dump-frames, the playground of each frame goes to a file
+    // of its own in the directory, numbered from 0.
+    dump_frames: Option<PathBuf>,
+    frames: u32,
+
+    // Where the inspection cursor is, if it's shown.  It only looks
+    // at the playground and leaves the game alone.
+    inspect: Option<(i16, i16)>,
+}
+
+// A game in progress.  The keys come in through handle_input, the
+// rules play out in step_simulation, and render_frame is all that
+// draws.
+struct GameState {
+    pgw: i16,
+    pgh: i16,
+    bd: Block,
+    pg: Block,
+    blk: Block,
+    queue: VecDeque<Block>,
+
+    // Whether the current block is a mercy one, see spawn.
+    mercy: bool,
+    score: u32,
+    multiplier: u32,
+
+    // Consecutive drops that scored.
+    chain: u32,
+
+    last_drop_time: time::SteadyTime,
+    last_mult_time: time::SteadyTime,
+
+    // When the player last dropped a block or took one back, for the
+    // drop grace.  Clocks and hourglasses move last_drop_time around,
+    // this stays put.
+    dropped_at: time::SteadyTime,
+
+    // When the game started, not counting pauses.
+    game_start: time::SteadyTime,
+
+    // The last block dropped and when, for the drop animation.
+    landing: Option<(Block, time::SteadyTime)>,
+
+    // Only the last drop can be taken back, and only once.
+    undo: Option<Undo>,
+
+    // Rotations and swaps since the last drop.
+    stalls: u32,
+    last_rotation: Option<time::SteadyTime>,
+    stats: Stats,
+    particles: Vec<Particle>,
+
+    // Whether the board is close to full, see in_danger.
+    danger: bool,
+
+    // Until when an exploded hourglass slows the gauges down.
+    slow_until: Option<time::SteadyTime>,
+
+    // When the tiles that exploded meteors rain down land, earliest
+    // first.
+    meteors: VecDeque<time::SteadyTime>,
+
+    // When the fast forward key was last pressed, see FAST_FACTOR.
+    fast_forward: Option<time::SteadyTime>,
+
+    curve: LevelCurve,
+    difficulty: Difficulty,
+
+    // The drop_time setting, in milliseconds.
+    drop_time: i64,
+    handicap: usize,
+    rng: StdRng,
+    source: Box<dyn BlockSource>,
+    bell: Bell,
+}
+
+impl GameState {
+    fn new(config: &Config, pgw: i16, pgh: i16, seed: u64, source: Box<dyn BlockSource>,
+           bell: Bell, now: time::SteadyTime) -> GameState {
+        let mut state = GameState {
+            pgw, pgh, bd: Block::new_border(pgw, pgh),
+            pg: Block::new(), blk: Block::new(), queue: VecDeque::new(), mercy: false,
+            score: 0, multiplier: 1, chain: 0,
+            last_drop_time: now, last_mult_time: now, dropped_at: now, game_start: now,
+            landing: None, undo: None, stalls: 0, last_rotation: None,
+            stats: Stats::default(), particles: Vec::new(), danger: false,
+            slow_until: None, meteors: VecDeque::new(),
+            fast_forward: None, curve: config.level_curve, difficulty: config.difficulty,
+            drop_time: config.drop_time as i64 * 1000,
+            handicap: config.handicap,
+            rng: SeedableRng::from_seed(&[seed as usize][..]),
+            source, bell,
+        };
+        state.deal(config.previews);
+        state
+    }
+
+    // The first blocks and the board they start on.
+    fn deal(&mut self, previews: usize) {
+        self.source.rewind();
+        self.blk = self.next_block().moved_to(2, 2);
+        let queue = (0..previews).map(|_| self.next_block().moved_to(1, 1)).collect();
+        self.queue = queue;
+        self.mercy = false;
+        self.pg = starting_board(self.difficulty, self.handicap, &self.bd, &self.blk,
+                                 self.pgw, self.pgh, &mut self.rng);
+    }
+
+    fn next_block(&mut self) -> Block {
+        let lvl = self.difficulty.tile_level(self.curve.level(self.score));
+        self.source.next(lvl, &mut self.rng)
+    }
+
+    fn drop_interval(&self) -> i64 {
+        self.difficulty.drop_interval(self.curve.level(self.score), self.drop_time)
+            * self.slowdown()
+    }
+
+    fn mult_interval(&self) -> i64 {
+        MULT_INTERVAL_MS * self.slowdown()
+    }
+
+    // How many times longer the gauges take, see SLOW_FACTOR.
+    fn slowdown(&self) -> i64 {
+        if self.slow_until.is_some() { SLOW_FACTOR } else { 1 }
+    }
+
+    // End the slowdown of an hourglass once it's over.
+    fn end_slowdown(&mut self, now: time::SteadyTime) {
+        if self.slow_until.is_some_and(|until| now >= until) {
+            self.set_slowdown(None, now);
+        }
+    }
+
+    // Start or end a slowdown.  The gauges keep what they have left,
+    // rather than jump along with their intervals.
+    fn set_slowdown(&mut self, until: Option<time::SteadyTime>, now: time::SteadyTime) {
+        let (drop_left, mult_left) = (self.drop_left(now), self.mult_left(now));
+        self.slow_until = until;
+        self.last_drop_time = self.last_drop_time
+            + time::Duration::milliseconds(drop_left - self.drop_left(now));
+        self.last_mult_time = self.last_mult_time
+            + time::Duration::milliseconds(mult_left - self.mult_left(now));
+    }
+
+    // Milliseconds left on the gauges.  They go below zero once the
+    // time is up, and a clock can take the drop gauge over its
+    // interval.
+    fn drop_left(&self, now: time::SteadyTime) -> i64 {
+        self.drop_interval() - (now - self.last_drop_time).num_milliseconds()
+    }
+
+    fn mult_left(&self, now: time::SteadyTime) -> i64 {
+        self.mult_interval() - (now - self.last_mult_time).num_milliseconds()
+    }
+
+    // What's left of a blitz game of the given length.  Pauses don't
+    // count, see hold.
+    fn blitz_left(&self, limit: i64, now: time::SteadyTime) -> i64 {
+        limit - (now - self.game_start).num_milliseconds()
+    }
+
+    // Pick up a saved game where it was left.
+    fn restore(&mut self, saved: SavedGame, previews: usize, now: time::SteadyTime) {
+        self.pg = saved.pg;
+        self.blk = saved.blk;
+        self.queue = saved.queue;
+        self.mercy = saved.mercy;
+        self.score = saved.score;
+        self.multiplier = saved.multiplier;
+
+        // The previews may have been set differently since.
+        self.queue.truncate(previews);
+        while self.queue.len() < previews {
+            let next = self.next_block().moved_to(1, 1);
+            self.queue.push_back(next);
+        }
+
+        self.last_drop_time = now - time::Duration::milliseconds(
+            self.drop_interval() - saved.drop_left);
+        self.last_mult_time = now - time::Duration::milliseconds(
+            MULT_INTERVAL_MS - saved.mult_left);
+        self.dropped_at = now;
+    }
+
+    // The game to save, with the gauges as they were at the given
+    // time.
+    fn to_saved(&self, when: time::SteadyTime) -> SavedGame {
+        SavedGame {
+            width: self.pgw, height: self.pgh,
+            pg: self.pg.clone(), blk: self.blk.clone(), queue: self.queue.clone(),
+            mercy: self.mercy, score: self.score, multiplier: self.multiplier,
+            drop_left: self.drop_left(when),
+            mult_left: self.mult_left(when),
+        }
+    }
+
+    fn restart(&mut self, previews: usize, now: time::SteadyTime) {
+        self.score = 0;
+        self.multiplier = 1;
+        self.chain = 0;
+        self.deal(previews);
+        self.stalls = 0;
+        self.stats = Stats::default();
+        self.undo = None;
+        self.particles.clear();
+        self.last_drop_time = now;
+        self.last_mult_time = now;
+        self.dropped_at = now;
+        self.game_start = now;
+        self.slow_until = None;
+        self.meteors.clear();
+        self.fast_forward = None;
+    }
+
+    // Bring in the next block.  With the mercy rule, a nearly full
+    // playground gets a block of plain tiles instead, and the one at
+    // the front of the queue waits for the next drop.
+    fn spawn(&mut self, config: &Config) {
+        self.mercy = config.mercy
+            && occupancy(&self.pg, self.pgw, self.pgh) >= MERCY_OCCUPANCY;
+        if self.mercy {
+            self.blk = Block::new_plain(&mut self.rng).moved_to(2, 2);
+        } else {
+            self.blk = self.queue.pop_front().unwrap().moved(1, 1);
+            let next = self.next_block().moved_to(1, 1);
+            self.queue.push_back(next);
+        }
+    }
+
+    // Clear the corner where new blocks appear, and the particles
+    // show it was swept.
+    fn sweep_spawn(&mut self) {
+        let (x0, y0) = (self.pg.x, self.pg.y);
+        let (swept, rest): (Vec<_>, Vec<_>) = self.pg.tiles.iter().cloned()
+            .partition(|&(x, y, _)| cmp::max(x0 + x, y0 + y) <= SPAWN_SIZE);
+        self.pg.tiles = rest;
+        for (x, y, _) in swept {
+            self.particles.push(Particle::new(4. * (x0 + x) as f32 + 1.,
+                                              2. * (y0 + y) as f32 + 1.,
+                                              "~~~".to_string(), 400));
+        }
+    }
+
+    // Stop the clocks for the time since the given one, as if it
+    // didn't pass.
+    fn hold(&mut self, since: time::SteadyTime, now: time::SteadyTime) {
+        let held = now - since;
+        self.last_drop_time = self.last_drop_time + held;
+        self.last_mult_time = self.last_mult_time + held;
+        self.dropped_at = self.dropped_at + held;
+        self.game_start = self.game_start + held;
+        self.slow_until = self.slow_until.map(|until| until + held);
+        for when in &mut self.meteors {
+            *when = *when + held;
+        }
+        if let Some(ref mut u) = self.undo {
+            u.last_mult_time = u.last_mult_time + held;
+            u.slow_until = u.slow_until.map(|until| until + held);
+            for when in &mut u.meteors {
+                *when = *when + held;
+            }
+        }
+    }
+
+    // Explode what's ready in pg and score it, with popups at the
+    // given place.  A detonation that scores extends the chain, one
+    // that doesn't breaks it.
+    fn detonate(&mut self, (x, y): (i16, i16), config: &Config, now: time::SteadyTime) {
+        let (popups, template) = (config.popups, &config.popup_template[..]);
+        let Explosion {exploded, hits, dmult, time: extra, slowdown, meteors}
+            = self.pg.explode();
+        if slowdown {
+            self.set_slowdown(Some(now + time::Duration::milliseconds(SLOW_MS)), now);
+        }
+        for _ in 0..meteors {
+            for _ in 0..METEOR_TILES {
+                let after = *self.meteors.back().unwrap_or(&now);
+                self.meteors.push_back(after + time::Duration::milliseconds(METEOR_MS));
+            }
+        }
+        // The gauge can't get fuller than full, see paint_gauge, so a
+        // clock holds it there for a while.
+        self.last_drop_time = self.last_drop_time + time::Duration::milliseconds(extra);
+        if dmult != 0 {
+            self.bell.ring(Cue::Multiplier);
+        } else if hits > 0 {
+            self.bell.ring(Cue::Explosion);
+        }
+        self.chain = if hits > 0 { self.chain + 1 } else { 0 };
+        let scoring = Scoring::new(hits, self.multiplier, self.chain);
+        let bonus = scoring.total();
+        if self.chain > 1 {
+            // Yellow, like the centerpieces.
+            self.particles.push(Particle::new(4. * x as f32, 2. + 2. * y as f32,
+                                              format!("Chain x{}", self.chain), 1500)
+                                .colored(3));
+        }
+        self.score += bonus;
+        self.stats.detonated(&exploded, bonus);
+
+        // The breakdown has the base points at the tiles, and how they
+        // add up stacked where the block landed.
+        if config.breakdown {
+            for (xx, yy, points) in bonus_breakdown(&exploded) {
+                if points > 0 {
+                    self.particles.push(Particle::new(4. * xx as f32, 2. * yy as f32,
+                                                      popups.score(points, template), 5000)
+                                        .moving(0., -POPUP_DRIFT));
+                }
+            }
+            if bonus > 0 {
+                for (i, line) in scoring.lines().into_iter().enumerate() {
+                    self.particles.push(Particle::new(4. * x as f32 + 4., 2. * y as f32 + i as f32,
+                                                      line, 5000)
+                                        .moving(0., -POPUP_DRIFT)
+                                        .colored(PAIR_HUD));
+                }
+            }
+        } else if exploded.len() > BIG_EXPLOSION {
+            self.particles.push(Particle::burst(4. * x as f32 + 2., 2. * y as f32 + 1.,
+                                                popups.score(bonus, template), 2000)
+                                .colored(3));
+        } else if bonus > 0 {
+            self.particles.push(Particle::new(4. * x as f32, 2. * y as f32,
+                                              popups.score(bonus, template), 5000)
+                                .moving(0., -POPUP_DRIFT));
+        }
+
+        if dmult != 0 {
+            self.multiplier = apply_dmult(self.multiplier, dmult);
+            self.last_mult_time = now;
+            self.particles.push(Particle::new(4. * x as f32, 1. + 2. * y as f32,
+                                              popups.multiplier(dmult, template), 5000));
+        }
+    }
+
+    // Make the moved block the current one, if it fits.
+    fn try_place(&mut self, moved: Block,
+                 splashes: &mut Vec<(i16, i16, LiquidType)>) -> bool {
+        if moved.intersects(&self.bd) {
+            false
+        } else if moved.collides_with(&self.pg) {
+            let (moved2, pg2, splashed) = Block::collide(moved, &self.pg);
+            if moved2.collides_with(&pg2) {
+                false
+            } else {
+                self.pg = pg2;
+                splashes.extend(splashed);
+                self.blk = moved2;
+                true
+            }
+        } else {
+            self.blk = moved;
+            true
+        }
+    }
+
+    // A turned block that doesn't fit gets kicked aside by a field if
+    // that helps.  Each place is checked in full against the border
+    // and the playground, so a kick can't carry the block through a
+    // wall or a permanent tile.
+    fn try_turn(&mut self, turned: Block, splashes: &mut Vec<(i16, i16, LiquidType)>) {
+        for &(dx, dy) in &[(0, 0), (-1, 0), (1, 0), (0, -1)] {
+            if self.try_place(turned.moved(dx, dy), splashes) {
+                return;
+            }
+        }
+    }
+
+    // Take the keys of the frame and do what they ask for, up to the
+    // first drop.  With drop_due, the drop gauge ran out and no keys
+    // are taken until the next frame.
+    fn handle_input(&mut self, ctl: &mut Controls, feed: &mut Feed, config: &mut Config,
+                    drop_due: bool) -> Result<Input, Ending> {
+        let mut input = Input {drop: drop_due, .. Input::default()};
+
+        // Wait a frame for input unless some is already queued, then
+        // drain whatever else is pending, so that quick key sequences
+        // aren't lost.
+        for ch in feed.keys(if ctl.input.is_empty() { ctl.frame_ms } else { 0 }) {
+            if ctl.input.len() < MAX_QUEUED_KEYS {
+                ctl.input.push_back(ch);
+            }
+        }
+
+        // The demo goes a step at a time, so that it can be watched.
+        // Should the block get stuck on the way, it's dropped where
+        // it is.
+        let now = feed.now();
+        if feed.demo_running() && ctl.input.is_empty()
+            && (now - ctl.demo_time).num_milliseconds() >= DEMO_STEP_MS {
+            ctl.demo_time = now;
+            if ctl.demo_target.is_none() {
+                ctl.demo_target = suggest_move(&self.blk, &self.pg, &self.bd,
+                                               self.pgw, self.pgh);
+                ctl.demo_steps = 0;
+            }
+            ctl.demo_steps += 1;
+            let action = match ctl.demo_target {
+                Some(ref target) if ctl.demo_steps <= DEMO_MAX_STEPS =>
+                    demo_action(&self.blk, target),
+                _ => Action::Drop,
+            };
+            if let Some(key) = ctl.keys.key(action) {
+                ctl.input.push_back(key);
+            }
+        }
+        if !ctl.input.is_empty() {
+            feed.keep();
+        }
+
+        // Process the keys in order.  A drop brings in a new block,
+        // so once that happens, the rest of the queue waits for the
+        // next frame.
+        while !input.drop {
+            let ch = match ctl.input.pop_front() {
+                Some(ch) => ch,
+                None => break,
+            };
+
+            if ch == '?' as i32 {
+                ctl.show_keys = !ctl.show_keys;
+                continue;
+            }
+
+            if ch == nc::KEY_F1 && ctl.debug {
+                ctl.overlay = !ctl.overlay;
+                continue;
+            }
+
+            // F2 dumps the board for a bug report.  It can then be
+            // looked at with --simulate.
+            if ch == nc::KEY_F2 && ctl.debug {
+                let face = match fs::write(DEBUG_SNAPSHOT, self.pg.to_snapshot() + "\n") {
+                    Ok(()) => format!("Board saved to {}", DEBUG_SNAPSHOT),
+                    Err(err) => format!("Couldn't save board: {}", err),
+                };
+                self.particles.push(Particle::new(1., 1., face, 2000));
+                continue;
+            }
+
+            // The layout follows the terminal by itself, unless it
+            // no longer fits.  The game is on hold until it does.
+            if ch == nc::KEY_RESIZE {
+                let layout = config.hud.resolve(self.pgw, self.pgh);
+                let (cols, lines) = screen_size(self.pgw, self.pgh, layout);
+                let resize_start = feed.now();
+                if !wait_for_room(feed, cols, lines) {
+                    return Err(Ending::Stopped);
+                }
+                self.hold(resize_start, feed.now());
+                continue;
+            }
+
+            // While inspecting, the moves go to the cursor instead of
+            // the block.  The cursor is on screen, so it's not mirrored.
+            if let Some((x, y)) = ctl.inspect {
+                let (dx, dy) = match ctl.keys.action(ch) {
+                    Some(Action::Left) => (-1, 0),
+                    Some(Action::Right) => (1, 0),
+                    Some(Action::Up) => (0, -1),
+                    Some(Action::Down) => (0, 1),
+                    _ => (0, 0),
+                };
+                if (dx, dy) != (0, 0) {
+                    ctl.inspect = Some(((x + dx).clamp(0, self.pgw - 1),
+                                        (y + dy).clamp(0, self.pgh - 1)));
+                    continue;
                 }
             }
+
+            // The demo presses the keys for what it wants done, so
+            // it's never mirrored.
+            let action = match ctl.keys.action(ch) {
+                Some(action) if config.mirrored && !feed.demo_running() => action.mirrored(),
+                Some(action) => action,
+                None => continue,
+            };
+
+            match action {
+                Action::Detonate | Action::Undo | Action::Inspect
+                    | Action::FastForward | Action::Pause | Action::Quit => {},
+                _ => self.stats.moves += 1,
+            }
+
+            match action {
+                Action::Left => {
+                    let moved = self.blk.moved(-1, 0);
+                    self.try_place(moved, &mut input.splashes);
+                },
+                Action::Right => {
+                    let moved = self.blk.moved(1, 0);
+                    self.try_place(moved, &mut input.splashes);
+                },
+                Action::Up => {
+                    let moved = self.blk.moved(0, -1);
+                    self.try_place(moved, &mut input.splashes);
+                },
+                Action::Down => {
+                    let moved = self.blk.moved(0, 1);
+                    self.try_place(moved, &mut input.splashes);
+                },
+                Action::Swap => {
+                    let moved = self.queue[0].moved_to(self.blk.x, self.blk.y);
+                    if !block_collides(&moved, &self.bd, &self.pg) {
+                        self.queue[0] = self.blk.moved_to(1, 1);
+                        self.blk = moved;
+                        self.mercy = false;
+                        self.stalls += 1;
+
+                        if !config.swapped {
+                            config.swapped = true;
+                            // Failing to remember this is not worth
+                            // interrupting the game over.
+                            let _ = config.save();
+                        }
+                    }
+                },
+
+                Action::Rotate | Action::RotateBack => {
+                    let debounce = time::Duration::milliseconds(ROTATE_DEBOUNCE_MS);
+                    let now = feed.now();
+                    if self.last_rotation.is_some_and(|last| now - last < debounce) {
+                        continue;
+                    }
+                    self.last_rotation = Some(now);
+
+                    let turned = match action {
+                        Action::Rotate => self.blk.turned(),
+                        _ => self.blk.turned_ccw(),
+                    };
+                    self.try_turn(turned, &mut input.splashes);
+                    self.stalls += 1;
+                    self.stats.rotations += 1;
+                },
+                // Flips don't get kicked aside like turns, the block
+                // either fits as it is or stays unflipped.
+                Action::FlipH | Action::FlipV => {
+                    let flipped = match action {
+                        Action::FlipH => self.blk.flipped_h(),
+                        _ => self.blk.flipped_v(),
+                    };
+                    if self.try_place(flipped, &mut input.splashes) {
+                        self.stalls += 1;
+                    }
+                },
+                Action::FastForward => {
+                    let now = feed.now();
+                    let held = self.fast_forward
+                        .map_or(FAST_HOLD_MS, |last| (now - last).num_milliseconds());
+                    let ahead = cmp::min(cmp::min(held, FAST_HOLD_MS) * (FAST_FACTOR - 1),
+                                         self.drop_left(now) - FAST_FLOOR_MS);
+                    if ahead > 0 {
+                        self.last_drop_time = self.last_drop_time
+                            - time::Duration::milliseconds(ahead);
+                    }
+                    self.fast_forward = Some(now);
+                },
+                Action::Drop => {
+                    let grace = time::Duration::milliseconds(config.drop_grace as i64);
+                    if feed.now() - self.dropped_at > grace {
+                        input.drop = true;
+                    }
+                },
+                // Down as far as the block goes without touching
+                // anything, then drop.
+                Action::HardDrop => {
+                    let grace = time::Duration::milliseconds(config.drop_grace as i64);
+                    if feed.now() - self.dropped_at > grace {
+                        let dropped = self.blk.hard_dropped(&self.pg, &self.bd);
+                        input.hard_drop += (dropped.y - self.blk.y) as u32;
+                        self.blk = dropped;
+                        input.drop = true;
+                    }
+                },
+                /*
+                ' ' => self.blk = Block::new_random(self.curve.level(self.score),
+                                                    &mut self.rng).moved_to(2, 2),
+                '+' => self.score += 500,
+                '*' => self.multiplier += 1,
+                */
+                Action::Detonate => {
+                    if config.detonation == Detonation::Manual {
+                        let at = (self.blk.x, self.blk.y);
+                        self.detonate(at, config, feed.now());
+                    }
+                },
+                Action::Undo => {
+                    if let Some(u) = self.undo.take() {
+                        self.pg = u.pg;
+                        self.blk = u.blk;
+                        self.queue = u.queue;
+                        self.mercy = u.mercy;
+                        self.score = u.score;
+                        self.multiplier = u.multiplier;
+                        self.chain = u.chain;
+                        self.stats = u.stats;
+                        self.last_mult_time = u.last_mult_time;
+                        self.slow_until = u.slow_until;
+                        self.meteors = u.meteors;
+                        self.last_drop_time = feed.now();
+                        self.dropped_at = feed.now();
+                        self.stalls = 0;
+                    }
+                },
+                Action::Inspect => {
+                    ctl.inspect = match ctl.inspect {
+                        Some(_) => None,
+                        None => Some((self.blk.x, self.blk.y)),
+                    };
+                },
+                Action::Quit => {
+                    let ask_start = feed.now();
+                    if confirm_quit(feed, 2 * self.pgw - 7, self.pgh - 1, ctl.look.ascii) {
+                        return Err(Ending::Quit(ask_start));
+                    }
+                    self.hold(ask_start, feed.now());
+                    ctl.input.clear();
+                },
+                Action::Pause => {
+                    let pause_start = feed.now();
+                    match pause(feed, &ctl.keys, 2 * self.pgw - 9, self.pgh - 3,
+                                ctl.look.ascii) {
+                        PauseAction::Resume => self.hold(pause_start, feed.now()),
+                        PauseAction::Restart => self.restart(config.previews, feed.now()),
+                        PauseAction::Quit => return Err(Ending::Quit(pause_start)),
+                    }
+
+                    // Whatever was typed ahead of the pause is
+                    // stale now.
+                    ctl.input.clear();
+                },
+            }
         }
-        false
+
+        Ok(input)
     }
 
-    fn spill(x: i16, y: i16, spills: &mut Vec<(i16, i16, LiquidType)>, liquid: LiquidType) {
-        for &(dx, dy) in &[(0, 0), (0, 1), (1, 0), (0, -1), (-1, 0)] {
-            spills.push((x+dx, y+dy, liquid));
+    // Play out the rest of the frame: stalling, spills, the drop and
+    // the multiplier gauge, which is due if it ran out.  The demo
+    // picks a new target once its block is dropped.
+    fn step_simulation(&mut self, input: Input, mult_due: bool, ctl: &mut Controls,
+                       config: &Config, now: time::SteadyTime) -> Option<Ending> {
+        if let GameMode::Blitz(limit) = config.game_mode {
+            if self.blitz_left(limit, now) <= 0 {
+                return Some(Ending::Over);
+            }
+        }
+        self.end_slowdown(now);
+
+        if self.stalls >= STALL_LIMIT {
+            match config.stalling {
+                StallPenalty::Off => {},
+                StallPenalty::Garbage => {
+                    if let Some((x, y)) = garbage_field(&self.pg, &self.bd, &self.blk,
+                                                        self.pgw, self.pgh, &mut self.rng) {
+                        self.pg.tiles.push((x, y, TileType::Plain(0)));
+                    }
+                },
+                StallPenalty::Hurry => {
+                    self.last_drop_time = self.last_drop_time
+                        - time::Duration::milliseconds(STALL_HURRY_MS);
+                },
+            }
+            self.stalls = 0;
         }
-    }
 
-    fn explode(&mut self) -> (Vec<(i16, i16, TileType)>, u32, i32) {
-        let mut killlist = Vec::new();
+        while self.meteors.front().is_some_and(|&when| when <= now) {
+            self.meteors.pop_front();
+            let tt = TileType::new_random(self.curve.level(self.score), &mut self.rng);
+            if let Some((x, y)) = meteor_field(&self.pg, &self.bd, &self.blk,
+                                               self.pgw, self.pgh, &mut self.rng) {
+                self.pg.tiles.push((x, y, tt));
+                self.particles.push(Particle::new(4. * x as f32 + 1., 2. * y as f32 + 1.,
+                                                  "▾▾▾".to_string(), 400));
+            }
+        }
 
-        {
-            'next: for &(xx, yy, tt) in &self.tiles {
-                let mut sublist = Vec::new();
-                for &(dx, dy) in tt.explode_shape() {
-                    let x2 = self.x + xx + dx;
-                    let y2 = self.y + yy + dy;
-                    match self.at(x2, y2) {
-                        None => continue 'next,
-                        Some(tt2) => if tt.explodes(tt2) {
-                            sublist.push((x2, y2));
-                        } else {
-                            continue 'next
-                        },
-                    }
+        // Show what the spills did to the tiles that were moved into
+        // them.  Acid dissolves the tile, glue holds it in place.
+        for (x, y, liquid) in input.splashes {
+            let face = match liquid {
+                LiquidType::Acid => "░░░",
+                LiquidType::Glue => "≈≈≈",
+            };
+            self.particles.push(Particle::new(4. * x as f32 + 1., 2. * y as f32 + 1.,
+                                              face.to_string(), 400));
+        }
+
+        if self.blk.tiles.is_empty() || input.drop {
+            let before = Undo {pg: self.pg.clone(), blk: self.blk.clone(),
+                               queue: self.queue.clone(), mercy: self.mercy,
+                               score: self.score, multiplier: self.multiplier,
+                               chain: self.chain, stats: self.stats.clone(),
+                               last_mult_time: self.last_mult_time,
+                               slow_until: self.slow_until, meteors: self.meteors.clone()};
+            if self.blk.drop(&mut self.pg, &self.bd) {
+                self.undo = Some(before);
+                self.score += HARD_DROP_POINTS * input.hard_drop;
+                if config.drop_animation && !config.reduce_motion {
+                    self.landing = Some((self.blk.clone(), now));
+                }
+                self.last_drop_time = now;
+                self.dropped_at = now;
+                ctl.demo_target = None;
+                self.stalls = 0;
+                self.stats.dropped(self.multiplier);
+                if config.detonation == Detonation::Auto {
+                    let at = (self.blk.x, self.blk.y);
+                    self.detonate(at, config, now);
+                } else {
+                    self.chain = 0;
                 }
-                for i in sublist {
-                    killlist.push(i);
+
+                self.spawn(config);
+                if block_collides(&self.blk, &self.bd, &self.pg) {
+                    // With manual detonation, a full board sets off
+                    // whatever is ready to explode.  The game is only
+                    // over if that doesn't make room for the new
+                    // block.
+                    if config.detonation == Detonation::Manual {
+                        let at = (self.blk.x, self.blk.y);
+                        self.detonate(at, config, now);
+                    }
+                    if block_collides(&self.blk, &self.bd, &self.pg) {
+                        match config.game_mode {
+                            GameMode::Classic | GameMode::Blitz(_) => return Some(Ending::Over),
+                            GameMode::Zen => self.sweep_spawn(),
+                        }
+                    }
                 }
             }
         }
 
-        let mut exploded = Vec::new();
+        if mult_due {
+            self.bell.ring(Cue::Multiplier);
+            self.multiplier = if self.multiplier > 1 { self.multiplier - 1 }
+            		      else { self.multiplier + 1 };
+            self.last_mult_time = now;
+        } else if self.multiplier == 1 {
+            self.last_mult_time = now;
+        }
 
-        fn handle_xp_action(xa: ExplodeAction, xx: i16, yy: i16,
-                            spills: &mut Vec<(i16, i16, LiquidType)>,
-                            rtiles: &mut Vec<(i16, i16, TileType)>) -> i32 {
-            match xa {
-                ExplodeAction::Remove => {
-                    0
-                },
-                ExplodeAction::Convert(tt2) => {
-                    rtiles.push((xx, yy, tt2));
-                    0
-                },
-                ExplodeAction::Spill(liquid) => {
-                    Block::spill(xx, yy, spills, liquid);
-                    0
-                },
-                ExplodeAction::Plus => 1,
-                ExplodeAction::Minus => -1,
-                ExplodeAction::Complex(a, b) => {
-                    handle_xp_action(*a, xx, yy, spills, rtiles)
-                        + handle_xp_action(*b, xx, yy, spills, rtiles)
-                },
+        // The warning comes once as the board gets close to full, the
+        // border stays in the danger color until it's cleared.
+        let danger = in_danger(&self.pg, self.pgw, self.pgh);
+        if danger && !self.danger {
+            self.bell.ring(Cue::Danger);
+            self.particles.push(Particle::new(4. * (SPAWN_SIZE + 1) as f32, 2.,
+                                              "DANGER".to_string(), 2000)
+                                .colored(PAIR_GAUGE_LOW));
+        }
+        self.danger = danger;
+        None
+    }
+
+    fn render_frame(&self, ctl: &Controls, config: &Config, demo: bool, now: time::SteadyTime) {
+        let look = ctl.look;
+        let mut grid = Grid::new(4 * self.pgw, 2 * self.pgh);
+        paint_background(&mut grid, config.background, look);
+
+        // Guide lines through the fields where new blocks appear.
+        grid.fill_rect(5, 3, 12, 6, Field::None);
+        for xx in 0..2 {
+            for yy in 0..2 {
+                grid.draw_rect(6 + 4 * xx, 3 + 2 * yy, 5, 3, Pen::Thin);
+            }
+        }
+
+        self.pg.paint(&mut grid, look);
+        if config.detonation == Detonation::Manual {
+            for (x, y) in self.pg.matches() {
+                grid.paint_decoration(4 * x + 1, 2 * y + 1, "▸");
+            }
+        } else {
+            self.blk.ghost(&self.pg, &self.bd).paint_ghost(&mut grid, look);
+        }
+        if config.guide {
+            self.blk.paint_guide(&mut grid, &self.pg, &self.bd);
+        }
+
+        // Blocks land where they are, so there's nowhere for them to
+        // fall.  Instead, a dashed outline closes in on the block as
+        // it comes down to the board.
+        if let Some((ref landed, when)) = self.landing {
+            let left = DROP_ANIMATION_MS - (now - when).num_milliseconds();
+            let bounds = if left > 0 { landed.bounds() } else { None };
+            if let Some((x1, y1, x2, y2)) = bounds {
+                let spread = (DROP_ANIMATION_SPREAD as i64 * left
+                              / DROP_ANIMATION_MS) as i16 + 1;
+                let gx1 = cmp::max(4 * x1 - 2 * spread, 0);
+                let gy1 = cmp::max(2 * y1 - spread, 0);
+                let gx2 = cmp::min(4 * (x2 + 1) + 2 * spread, grid.w);
+                let gy2 = cmp::min(2 * (y2 + 1) + spread, grid.h);
+                grid.draw_rect(gx1, gy1, gx2 - gx1 + 1, gy2 - gy1 + 1, Pen::Dash);
+            }
+        }
+        self.bd.paint(&mut grid, look);
+        let border = if self.danger { PAIR_GAUGE_LOW } else { PAIR_BORDER };
+        for &(dx, dy, _) in &self.bd.tiles {
+            grid.color_drawings(4 * (self.bd.x + dx), 2 * (self.bd.y + dy), 5, 3, border);
+        }
+        self.blk.paint(&mut grid, look);
+        if self.mercy {
+            if let Some((x1, y1, _, _)) = self.blk.bounds() {
+                grid.paint_decoration(4 * x1, 2 * y1, "♥");
             }
+        }
+        if let Some((x, y)) = ctl.inspect {
+            grid.draw_rect(4 * x, 2 * y, 5, 3, Pen::Thik);
+            grid.color_drawings(4 * x, 2 * y, 5, 3, PAIR_HUD);
+        }
+
+        let mut gridlets = Vec::new();
+        let shown = if config.blind { 0 } else { self.queue.len() };
+        for next in self.queue.iter().take(shown) {
+            let mut gridlet = Grid::new(12, 6);
+            next.paint(&mut gridlet, look);
+            gridlets.push(gridlet);
+        }
+
+
+        for p in &self.particles {
+            p.paint(&mut grid);
+        }
+
+        // A frame missing from the dump is not worth stopping the game
+        // over.
+        if let Some(ref dir) = ctl.dump_frames {
+            let path = dir.join(format!("{:06}.txt", ctl.frames - 1));
+            let _ = fs::write(path, grid.to_string());
+        }
+
+        // The frame goes around everything, so it's only there if
+        // the terminal has room to spare on all sides.
+        let (cols, lines) = terminal_size();
+        let layout = config.hud.resolve(self.pgw, self.pgh);
+        let (need_cols, need_lines) = screen_size(self.pgw, self.pgh, layout);
+        let framed = config.frame && cols >= need_cols + 2 && lines >= need_lines + 2;
+        let (ox, oy) = if framed { (1, 1) } else { (0, 0) };
+
+        nc::erase();
+        if framed {
+            let (w, h) = (need_cols as i16 + 1, need_lines as i16 + 1);
+            let mut frame = Grid::new(w, h);
+            frame.paint_wall(0, 0, w, Direction::Right, true, Pen::Thik);
+            frame.paint_wall(0, h, w, Direction::Right, true, Pen::Thik);
+            frame.paint_wall(0, 0, h, Direction::Down, true, Pen::Thik);
+            frame.paint_wall(w, 0, h, Direction::Down, true, Pen::Thik);
+            frame.render(0, 0, look.ascii);
+        }
+        grid.render(ox as i16, oy as i16, look.ascii);
+        for (i, gridlet) in gridlets.iter().enumerate() {
+            gridlet.render(grid.w + 1 + ox as i16, i as i16 * (gridlet.h + 1) + oy as i16,
+                           look.ascii);
+        }
+
+        let hud_at = match layout {
+            HudLayout::Below => (ox, oy + need_lines - HUD_BELOW_ROWS as i32),
+            _ => (grid.w as i32 + 14 + ox, oy),
+        };
+        self.render_hud(ctl, config, demo, now, layout, hud_at);
+
+        // The bar is below what screen_size asks for, so it's only
+        // there if the terminal has a line to spare.  While inspecting,
+        // it tells about the tile under the cursor instead of the keys.
+        let bar_y = match layout {
+            HudLayout::Below => need_lines + 2 * oy,
+            _ => grid.h as i32 + 1 + 2 * oy,
         };
+        let bar = match ctl.inspect {
+            Some((x, y)) => Some(match self.pg.at(x, y) {
+                Some(tt) => tt.description().replace("\n", " "),
+                None => "Nothing here.".to_string(),
+            }),
+            None if ctl.show_keys => Some(ctl.key_hint.clone()),
+            None => None,
+        };
+        if let Some(bar) = bar {
+            if bar_y < lines {
+                let bar: String = bar.chars().take(cols as usize).collect();
+                print_colored(bar_y, 0, &bar, PAIR_HUD);
+            }
+        }
 
-        let mut hits = 0;
-        let mut dmult = 0;
-        {
-            let mut rtiles = Vec::new();
-            let mut spills = Vec::new();
-            'next2: for &(xx, yy, tt) in &self.tiles {
-                for &(x2, y2) in &killlist {
-                    if self.x + xx == x2 && self.y + yy == y2 {
-                        exploded.push((xx, yy, tt));
-                        dmult += handle_xp_action(tt.explode(), xx, yy,
-                                                  &mut spills, &mut rtiles);
-                        hits += tt.bonus();
-                        continue 'next2;
-                    }
-                }
-                rtiles.push((xx, yy, tt));
+        nc::refresh();
+    }
+
+    // The gauges, the score and the rest of the side panel, starting
+    // at x, y.  It's one column, or with HudLayout::Below, several.
+    fn render_hud(&self, ctl: &Controls, config: &Config, demo: bool, now: time::SteadyTime,
+                  layout: HudLayout, (x, y): (i32, i32)) {
+        let mut rows: Vec<Option<(String, i16)>> = vec![None; HUD_ROWS];
+
+        let still = config.reduce_motion;
+        rows[0] = Some(paint_gauge(self.drop_left(now), self.drop_interval(), still));
+        rows[1] = Some((format!("Score: {}", config.score_format.format(self.score)),
+                        PAIR_HUD));
+        rows[2] = Some((format!("Level: {}", self.curve.level(self.score)), PAIR_HUD));
+        if config.hints && !config.swapped {
+            let arrow = if ctl.look.ascii { "<-" } else { "⇦" };
+            rows[3] = Some((format!("{} swap", arrow), PAIR_HUD));
+        }
+
+        rows[4] = Some(paint_gauge(self.mult_left(now), self.mult_interval(), still));
+        rows[5] = Some((format!("Multi: x{}", self.multiplier), PAIR_HUD));
+
+        let played = (now - self.game_start).num_seconds();
+        rows[6] = Some((format!("Time: {:02}:{:02}", played / 60, played % 60), PAIR_HUD));
+        if played > 0 {
+            rows[8] = Some((format!("Moves/min: {}", self.stats.moves as i64 * 60 / played),
+                            PAIR_HUD));
+        }
+
+        if let Some(until) = self.slow_until {
+            let left = cmp::max((until - now).num_milliseconds(), 0);
+            rows[9] = Some((format!("½ Slow: {}s", (left + 999) / 1000), PAIR_GAUGE_PLENTY));
+        }
+
+        if config.stalling != StallPenalty::Off
+            && self.stalls + STALL_WARNING >= STALL_LIMIT {
+            rows[7] = Some((format!("Stalling! {}", STALL_LIMIT - self.stalls), PAIR_HUD));
+        }
+
+        if let GameMode::Blitz(limit) = config.game_mode {
+            let left = cmp::max(self.blitz_left(limit, now), 0) / 1000;
+            let (blitzbar, blitz_color) = paint_gauge(self.blitz_left(limit, now), limit, still);
+            rows[10] = Some((blitzbar, blitz_color));
+            rows[11] = Some((format!("Blitz: {}:{:02}", left / 60, left % 60), blitz_color));
+        }
+
+        if demo {
+            rows[13] = Some(("Demo.".to_string(), PAIR_HUD));
+            rows[14] = Some(("Any key quits.".to_string(), PAIR_HUD));
+        }
+
+        if ctl.overlay {
+            rows[15] = Some((format!("Frame: {} ms", ctl.frame_time.num_milliseconds()), 0));
+            rows[16] = Some((format!("Particles: {}", self.particles.len()), 0));
+            rows[17] = Some((format!("Tiles: {}", self.pg.tiles.len()), 0));
+        }
+
+        for (i, row) in rows.into_iter().enumerate() {
+            if let Some((text, color)) = row {
+                let (rx, ry) = match layout {
+                    HudLayout::Below => (x + (i / HUD_BELOW_ROWS) as i32 * HUD_COLUMN_WIDTH,
+                                         y + (i % HUD_BELOW_ROWS) as i32),
+                    _ => (x, y + i as i32),
+                };
+                print_colored(ry, rx, &text, color);
             }
+        }
+    }
+}
+
+// With a seed given, every game deals the same sequence of blocks.  For
+// a replay, the settings and the seed need to match the recording.
+fn play(config: &mut Config, best: &mut u32, args: &Args, mode: Mode) -> AfterGame {
+    let (pgw, pgh) = (args.width, args.height);
+
+    // The demo presses the keys itself, so it needs to know them no
+    // matter how the player set them up.
+    let keys = match mode {
+        Mode::Demo => KeyBindings::preset(KeyPreset::Arrows),
+        _ => KeyBindings::load(config.keys),
+    };
+    let bell = Bell {muted: !args.sound || matches!(mode, Mode::Demo)};
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let practice = matches!(mode, Mode::Practice(_));
+    let source: Box<dyn BlockSource> = match mode {
+        Mode::Practice(ref script) => Box::new(script.clone()),
+        _ => match config.randomizer {
+            Randomizer::Pure => Box::new(Random),
+            Randomizer::Bag => Box::new(Bag::new()),
+        },
+    };
+
+    // A recording of a continued game would be missing its start, and
+    // one of practice the blocks, so none is made.
+    let (mut feed, saved) = match mode {
+        Mode::Live => (Feed::live(args.record.is_some()), None),
+        Mode::Continue(saved) => (Feed::live(false), Some(saved)),
+        Mode::Practice(_) => (Feed::live(false), None),
+        Mode::Replay(events) => (Feed::replay(events), None),
+        Mode::Demo => (Feed::demo(), None),
+    };
+    let can_save = !feed.replaying() && !feed.demo_running() && !practice;
+
+    let mut state = GameState::new(config, pgw, pgh, seed, source, bell, feed.now());
+    if let Some(saved) = saved {
+        state.restore(saved, config.previews, feed.now());
+    }
+
+    let look = config.look(args);
+    let mut ctl = Controls {
+        key_hint: keys.hint(look.ascii), keys, look,
+        frame_ms: 1000 / args.fps, debug: args.debug, input: VecDeque::new(),
+        demo_target: None, demo_steps: 0, demo_time: feed.now(),
+        show_keys: config.hints, overlay: false, frame_time: time::Duration::zero(),
+        inspect: None, dump_frames: args.dump_frames.clone(), frames: 0,
+    };
+    let mut last_frame = time::SteadyTime::now();
+
+    let ending = loop {
+        if !feed.frame() {
+            break Ending::Stopped;
+        }
+        let now = feed.now();
+        ctl.frame_time = time::SteadyTime::now() - last_frame;
+        ctl.frames += 1;
+        last_frame = last_frame + ctl.frame_time;
+
+        state.particles.retain(|p: &Particle| !p.dead());
+        for p in &mut state.particles {
+            p.update(config.reduce_motion);
+        }
+        state.render_frame(&ctl, config, feed.demo_running(), now);
+
+        // A gauge that ran out moves the game on by itself, which the
+        // recording needs to know about.
+        let drop_due = state.drop_left(now) <= 0;
+        let mult_due = state.mult_left(now) <= 0 && state.multiplier != 1;
+        if drop_due || mult_due {
+            feed.keep();
+        }
+
+        let input = match state.handle_input(&mut ctl, &mut feed, config, drop_due) {
+            Ok(input) => input,
+            Err(ending) => break ending,
+        };
+        let dropped = input.drop;
+        let now = feed.now();
+        if let Some(ending) = state.step_simulation(input, mult_due, &mut ctl, config, now) {
+            break ending;
+        }
+
+        // Failing to autosave is not worth interrupting the game
+        // over, the next drop tries again.
+        if dropped && can_save && config.autosave {
+            let _ = state.to_saved(now).autosave();
+        }
+    };
 
-            self.tiles = rtiles;
+    // However the game ended, it ended properly.
+    if can_save {
+        SavedGame::remove_autosave();
+    }
 
-            for (xx, yy, liquid) in spills {
-                if ! self.at(xx, yy).is_some() {
-                    self.tiles.push((xx, yy, TileType::Spillage(liquid)));
+    let mut after = AfterGame::Menu;
+    match ending {
+        Ending::Over => {
+            state.bell.ring(Cue::GameOver);
+            // Practice is no game for the high scores.
+            if feed.replaying() {
+                game_summary("End of replay.", state.score, &state.stats, config);
+            } else if practice {
+                game_summary("End of practice.", state.score, &state.stats, config);
+            } else if !feed.demo_running() {
+                let mut lifetime = Lifetime::load();
+                lifetime.record(state.score, state.curve.level(state.score), &state.stats,
+                                (feed.now() - state.game_start).num_seconds());
+                // Losing the totals is not worth bothering the player
+                // with on top of the game over.
+                let _ = lifetime.save();
+                if game_over(state.score, &state.stats, best, config) {
+                    after = AfterGame::Restart;
                 }
             }
+        },
+        // The gauges stand still from the moment the player quit.
+        Ending::Quit(when) => {
+            if can_save {
+                offer_save(&state.to_saved(when), 4 * pgw as i32 + 14);
+            }
+        },
+        Ending::Stopped => {},
+    }
+
+    if let (Some(events), Some(path)) = (feed.recorded(), args.record.as_ref()) {
+        let rec = Recording {config: config.clone(), seed,
+                             width: pgw, height: pgh, events};
+        if let Err(err) = rec.save(path) {
+            nc::erase();
+            nc::mvprintw(1, 1, &format!("Couldn't save the recording: {}", err));
+            nc::timeout(-1);
+            nc::getch();
         }
+    }
+    after
+}
+
+#[derive(Copy, Clone)]
+enum PauseAction {
+    Resume,
+    Restart,
+    Quit,
+}
+
+// Show the pause menu at x, y until the player picks something.  The
+// keys come from the feed, so that pauses get recorded and replayed.
+fn pause(feed: &mut Feed, keys: &KeyBindings, x: i16, y: i16,
+         ascii: bool) -> PauseAction {
+    let items = [("Resume", PauseAction::Resume),
+                 ("Restart", PauseAction::Restart),
+                 ("Quit to menu", PauseAction::Quit)];
+    let mut pos: usize = 0;
 
-        if exploded.len() > 12 {
-            dmult += (exploded.len() as i32 - 9) / 9;
+    loop {
+        let mut frame = Grid::new(17, 6);
+        frame.draw_rect(0, 0, 18, 7, Pen::Thik);
+        frame.paint_decoration(2, 1, "Pause.");
+        for (i, &(text, _)) in items.iter().enumerate() {
+            if i == pos {
+                frame.paint_decoration(1, 3 + i as i16, "➤");
+            }
+            frame.paint_decoration(3, 3 + i as i16, text);
         }
+        nc::erase();
+        frame.render(x, y, ascii);
 
-        (exploded, hits, dmult)
+        let key = feed.key();
+        match (keys.action(key), key) {
+            (Some(Action::Up), _) | (_, nc::KEY_UP) => {
+                pos = pos.saturating_sub(1);
+            },
+            (Some(Action::Down), _) | (_, nc::KEY_DOWN) => {
+                pos = cmp::min(pos + 1, items.len() - 1);
+            },
+            (Some(Action::Pause), _) => return PauseAction::Resume,
+            (Some(Action::Quit), _) => return PauseAction::Quit,
+            // A replay that ran out in the middle of a pause.
+            (_, nc::ERR) => return PauseAction::Quit,
+            (_, n) if n == '\r' as i32 => return items[pos].1,
+            _ => {},
+        }
     }
 }
 
-#[derive(Debug)]
-struct Particle {
-    x: f32,
-    y: f32,
-    face: String,
-    start: time::SteadyTime,
-    ttl: u32,
+// Ask over the board at x, y whether the player really meant to quit.
+// Only y says so.  Like the pause menu, the answer goes through the
+// feed.
+fn confirm_quit(feed: &mut Feed, x: i16, y: i16, ascii: bool) -> bool {
+    let mut frame = Grid::new(13, 2);
+    frame.draw_rect(0, 0, 14, 3, Pen::Thik);
+    frame.paint_decoration(1, 1, " Quit? y/n  ");
+    frame.render(x, y, ascii);
+
+    match feed.key() {
+        k if k == 'y' as i32 => true,
+        // A replay that ran out while asking.
+        nc::ERR => true,
+        _ => false,
+    }
 }
 
-impl Particle {
-    fn new(x: f32, y: f32, face: String, ttl: u32) -> Particle {
-        Particle {x: x, y: y, face: face, start: time::SteadyTime::now(), ttl: ttl}
+// Ask below the side panel whether to keep the game for later.
+fn offer_save(saved: &SavedGame, x: i32) {
+    nc::mvprintw(16, x, "Save the game? y/n");
+    nc::timeout(-1);
+    if nc::getch() != 'y' as i32 {
+        return;
+    }
+
+    if let Err(err) = saved.save() {
+        nc::mvprintw(17, x, &format!("Couldn't save: {}", err));
+        nc::getch();
+    }
+}
+
+// After the terminal was resized, wait until the game fits in it
+// again.  Returns false if the player quit instead.
+//
+// A replay doesn't look at the terminal, it waits for as long as the
+// recorded player did, so that the game stays the same.
+fn wait_for_room(feed: &mut Feed, cols: i32, lines: i32) -> bool {
+    loop {
+        let (maxx, maxy) = terminal_size();
+        let fits = if feed.replaying() {
+            !feed.key_pending()
+        } else {
+            maxx >= cols && maxy >= lines
+        };
+        if fits {
+            return true;
+        }
+
+        nc::erase();
+        nc::mvprintw(0, 0, "Window too small.");
+        nc::mvprintw(1, 0, &format!("Need {}x{}, have {}x{}.", cols, lines, maxx, maxy));
+        nc::mvprintw(2, 0, "Enlarge it to go on, q quits.");
+        nc::refresh();
+
+        let key = feed.key();
+        if key == nc::ERR || key == 'q' as i32 {
+            return false;
+        }
+    }
+}
+
+fn game_summary(heading: &str, score: u32, stats: &Stats, config: &Config) {
+    nc::erase();
+    logo();
+    nc::mvprintw(6, 1, heading);
+    nc::mvprintw(7, 1, &format!("Score: {}", config.score_format.format(score)));
+    print_stats(stats, 9, config.score_format);
+    nc::timeout(-1);
+    nc::getch();
+}
+
+// Let the player type in up to three characters at y, x.
+fn read_initials(y: i32, x: i32) -> String {
+    let mut name = String::new();
+    nc::timeout(-1);
+    loop {
+        nc::mvprintw(y, x, &format!("{:_<3}", name));
+        match nc::getch() {
+            nc::KEY_BACKSPACE => {
+                name.pop();
+            },
+            n => match n as u8 as char {
+                '\n' | '\r' if !name.is_empty() => return name,
+                c if c.is_ascii_alphanumeric() && name.len() < 3 => {
+                    name.push(c.to_ascii_uppercase());
+                },
+                _ => {},
+            },
+        }
+    }
+}
+
+fn print_scores(scores: &HighScores, y: i32, fmt: ScoreFormat, mark: Option<usize>) {
+    for (i, e) in scores.entries.iter().enumerate() {
+        if mark == Some(i) {
+            nc::mvprintw(y + i as i32, 1, "➤");
+        }
+        nc::mvprintw(y + i as i32, 3,
+                     &format!("{:>2}. {:<3}  {:>12}  {:<10}  {}",
+                              i + 1, e.name, fmt.format(e.score), e.date, e.mode));
+    }
+}
+
+// The high score table on its own, for the menu.
+fn scores(fmt: ScoreFormat) {
+    nc::erase();
+    logo();
+
+    let scores = HighScores::load();
+    if scores.entries.is_empty() {
+        nc::mvprintw(6, 3, "No high scores yet.");
+    } else {
+        nc::mvprintw(6, 3, &format!("{:<4}{:<3}  {:>12}  {:<10}  {}",
+                                    "#", "Who", "Score", "Date", "Mode"));
+        print_scores(&scores, 8, fmt, None);
+    }
+
+    nc::timeout(-1);
+    nc::getch();
+}
+
+// The totals over all games, for the menu.
+fn lifetime_stats(fmt: ScoreFormat) {
+    nc::erase();
+    logo();
+
+    let lifetime = Lifetime::load();
+    if lifetime.games == 0 {
+        nc::mvprintw(6, 3, "No games finished yet.");
+    } else {
+        let longest = lifetime.longest_game;
+        let lines = [
+            format!("Games played:    {}", lifetime.games),
+            format!("Total score:     {}", fmt.format(lifetime.score)),
+            format!("Tiles exploded:  {}", lifetime.exploded),
+            format!("Highest level:   {}", lifetime.best_level),
+            format!("Longest game:    {}:{:02}", longest / 60, longest % 60)];
+        for (i, line) in lines.iter().enumerate() {
+            nc::mvprintw(6 + i as i32, 3, line);
+        }
+    }
+
+    nc::timeout(-1);
+    nc::getch();
+}
+
+// Best is the best score of this session, updated if the game beat it.
+// Print the stats from line y on, and return the line after them.
+fn print_stats(stats: &Stats, y: i32, fmt: ScoreFormat) -> i32 {
+    let mut lines = vec![
+        format!("Best bonus:      {}", fmt.format(stats.best_bonus)),
+        format!("Rotations:       {}", stats.rotations),
+        format!("Longest streak:  {} drops with a multiplier", stats.longest_streak)];
+
+    let mut exploded: Vec<_> = stats.exploded.iter().collect();
+    exploded.sort_by(|a, b| b.1.cmp(a.1));
+    if !exploded.is_empty() {
+        lines.push(String::new());
+        lines.push("Exploded:".to_string());
+    }
+    for (name, count) in exploded {
+        lines.push(format!("  {:<14}{:>5}", name, count));
+    }
+
+    for (i, line) in lines.iter().enumerate() {
+        nc::mvprintw(y + i as i32, 1, line);
+    }
+    y + lines.len() as i32
+}
+
+// Returns whether the player wants to go again right away.
+fn game_over(score: u32, stats: &Stats, best: &mut u32, config: &Config) -> bool {
+    let fmt = config.score_format;
+    nc::erase();
+    logo();
+    nc::mvprintw(6, 1, "Game over.");
+    nc::mvprintw(7, 1, &format!("Score: {}   Level: {}",
+                                fmt.format(score), config.level_curve.level(score)));
+    if score >= *best {
+        nc::mvprintw(8, 1, "That's the best game this session!");
+        *best = score;
+    } else {
+        nc::mvprintw(8, 1, &format!("Session best: {} ({} short)",
+                                    fmt.format(*best),
+                                    fmt.format(*best - score)));
+    }
+
+    let y = print_stats(stats, 10, fmt);
+    nc::mvprintw(y + 1, 1, "Press any key.");
+    nc::timeout(-1);
+    nc::getch();
+
+    nc::erase();
+    logo();
+    nc::mvprintw(6, 1, &format!("Score: {}", fmt.format(score)));
+
+    let mut scores = HighScores::load();
+    let mut mark = None;
+    if scores.qualifies(score) {
+        nc::mvprintw(8, 1, "New high score!  Your initials: ");
+        let name = read_initials(8, 33);
+        let date = time::strftime("%Y-%m-%d", &time::now()).unwrap_or_default();
+        mark = scores.insert(HighScore {score, name, date,
+                                        mode: config.game_mode.name().to_string()});
+
+        if let Err(err) = scores.save() {
+            nc::mvprintw(9, 1, &format!("Couldn't save high scores: {}", err));
+        }
+    }
+
+    print_scores(&scores, 11, fmt, mark);
+    nc::mvprintw(12 + scores.entries.len() as i32, 1,
+                 "Press r to play again, any other key for the menu.");
+
+    nc::timeout(-1);
+    nc::getch() == 'r' as i32
+}
+
+#[derive(Copy, Clone)]
+enum MenuAction {
+    Play,
+    Continue,
+    Difficulty,
+    Demo,
+    Help,
+    Scores,
+    Stats,
+    Options,
+    Quit,
+}
+
+// What the pairs are called in theme files, in the order of the pairs.
+const THEME_ELEMENTS: [&str; 13] = ["plain", "killer", "centerpiece", "whopper",
+                                    "liquid", "plus_minus", "permanent",
+                                    "border", "dots", "hud",
+                                    "gauge_plenty", "gauge_half", "gauge_low"];
+
+const THEME_COLORS: [(&str, i16); 9] = [("default", -1),
+                                        ("black", nc::COLOR_BLACK),
+                                        ("red", nc::COLOR_RED),
+                                        ("green", nc::COLOR_GREEN),
+                                        ("yellow", nc::COLOR_YELLOW),
+                                        ("blue", nc::COLOR_BLUE),
+                                        ("magenta", nc::COLOR_MAGENTA),
+                                        ("cyan", nc::COLOR_CYAN),
+                                        ("white", nc::COLOR_WHITE)];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum ThemeName {
+    Default,
+    HighContrast,
+    Monochrome,
+
+    // ~/.config/grido/theme, with lines like "killer = white on red".
+    // Whatever it doesn't mention is as in the default theme.
+    File,
+}
+
+impl Choice for ThemeName {
+    fn all() -> &'static [ThemeName] {
+        static ALL: [ThemeName; 4] = [ThemeName::Default, ThemeName::HighContrast,
+                                      ThemeName::Monochrome, ThemeName::File];
+        &ALL
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ThemeName::Default => "default",
+            ThemeName::HighContrast => "high-contrast",
+            ThemeName::Monochrome => "monochrome",
+            ThemeName::File => "file",
+        }
     }
+}
+
+// The foreground and background of each color pair, -1 standing for
+// the terminal's own.
+#[derive(Clone, Debug)]
+struct Theme {
+    pairs: [(i16, i16); 13],
+}
 
-    fn paint(&self, grid: &mut Grid) {
-        grid.paint_decoration(self.x as i16, self.y as i16, &self.face);
+impl Theme {
+    fn builtin(name: ThemeName) -> Theme {
+        let d = -1;
+        let pairs = match name {
+            ThemeName::HighContrast => [
+                (nc::COLOR_WHITE, d), (nc::COLOR_WHITE, nc::COLOR_RED),
+                (nc::COLOR_BLACK, nc::COLOR_YELLOW), (nc::COLOR_WHITE, nc::COLOR_MAGENTA),
+                (nc::COLOR_BLACK, nc::COLOR_GREEN), (nc::COLOR_BLACK, nc::COLOR_CYAN),
+                (nc::COLOR_WHITE, nc::COLOR_BLUE),
+                (nc::COLOR_WHITE, d), (d, d), (nc::COLOR_WHITE, d),
+                (nc::COLOR_BLACK, nc::COLOR_GREEN), (nc::COLOR_BLACK, nc::COLOR_YELLOW),
+                (nc::COLOR_WHITE, nc::COLOR_RED)],
+            ThemeName::Monochrome => [(d, d); 13],
+            ThemeName::Default | ThemeName::File => [
+                (nc::COLOR_WHITE, d), (nc::COLOR_RED, d), (nc::COLOR_YELLOW, d),
+                (nc::COLOR_MAGENTA, d), (nc::COLOR_GREEN, d), (nc::COLOR_CYAN, d),
+                (nc::COLOR_BLUE, d),
+                (d, d), (d, d), (d, d),
+                (nc::COLOR_GREEN, d), (nc::COLOR_YELLOW, d), (nc::COLOR_RED, d)],
+        };
+        Theme {pairs}
     }
 
-    fn dead(&self) -> bool {
-        let ttl = time::Duration::milliseconds(self.ttl as i64);
-        time::SteadyTime::now() - self.start > ttl
+    fn path() -> Option<PathBuf> {
+        user_file("XDG_CONFIG_HOME", ".config", "theme")
     }
-}
-
-fn play() {
-    let (pgw, pgh) = (16 as i16, 12 as i16);
-    let mut score = 0;
-    let mut blk = Block::new_random(score).moved_to(2, 2);
-    let mut next = Block::new_random(score).moved_to(1, 1);
-    let bd = Block::new_border(pgw, pgh);
-    let mut pg = Block::new();
-    let mut particles: Vec<Particle> = Vec::new();
 
-    let mut last_drop_time = time::SteadyTime::now();
+    fn load(name: ThemeName) -> Result<Theme, String> {
+        if name != ThemeName::File {
+            return Ok(Theme::builtin(name));
+        }
 
-    let mut multiplier: u32 = 1;
-    let mut last_mult_time = last_drop_time;
+        let path = Theme::path().ok_or("nowhere to look for the theme file")?;
+        let text = fs::read_to_string(&path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        Theme::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
 
-    loop {
-        let mut drop = false;
-        let mut mult_drop = false;
+    fn parse(text: &str) -> Result<Theme, String> {
+        fn color(name: &str) -> Result<i16, String> {
+            THEME_COLORS.iter().find(|&&(n, _)| n == name).map(|&(_, c)| c)
+                .ok_or(format!("unknown color: {}", name))
+        }
 
-        particles.retain(|p: &Particle| !p.dead());
-        {
-            let mut grid = Grid::new(4 * pgw, 2 * pgh);
-            for xx in 0..grid.w {
-                for yy in 0..grid.h {
-                    if xx % 3 == yy % 3 {
-                        grid.paint_decoration(xx, yy, ".");
-                    }
-                }
+        let mut theme = Theme::builtin(ThemeName::Default);
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
 
-            grid.clear(5, 3, 12, 6);
-            for xx in 0..3 {
-                grid.paint_wall(6 + 4 * xx, 2, 6, Direction::Down,
-                                true, Pen::Thin);
-            }
-            for yy in 0..3 {
-                grid.paint_wall(4, 3 + 2 * yy, 12, Direction::Right,
-                                true, Pen::Thin);
-            }
+            let err = |msg: String| format!("line {}: {}", i + 1, msg);
+            let mut kv = line.splitn(2, '=');
+            let key = kv.next().unwrap_or("").trim();
+            let value = kv.next().ok_or(err("expected element = color".to_string()))?;
+            let idx = THEME_ELEMENTS.iter().position(|&e| e == key)
+                .ok_or(err(format!("unknown element: {}", key)))?;
+
+            let words: Vec<&str> = value.split_whitespace().collect();
+            theme.pairs[idx] = match words.len() {
+                1 => (color(words[0]).map_err(&err)?, -1),
+                3 if words[1] == "on" => (color(words[0]).map_err(&err)?,
+                                          color(words[2]).map_err(&err)?),
+                _ => return Err(err("expected color or color on color".to_string())),
+            };
+        }
+        Ok(theme)
+    }
 
-            pg.paint(&mut grid);
-            bd.paint(&mut grid);
-            blk.paint(&mut grid);
+    fn apply(&self) {
+        if !nc::has_colors() {
+            return;
+        }
 
-            let mut gridlet = Grid::new(12, 6);
-            next.paint(&mut gridlet);
+        for (i, &(fg, bg)) in self.pairs.iter().enumerate() {
+            nc::init_pair(i as i16 + 1, fg, bg);
+        }
+    }
+}
 
-            fn paint_gauge(start: &time::SteadyTime, limit: i64) -> (String, bool) {
-                let dtime = time::SteadyTime::now() - *start;
-                let mut remaining = limit - dtime.num_milliseconds();
-                if remaining < 0 {
-                    remaining = 0;
-                }
+// On monochrome terminals there's nothing to set up, and Grid::render
+// then doesn't use the pairs at all.
+fn init_colors() {
+    if !nc::has_colors() {
+        return;
+    }
 
-                // 96 is 12 * 8: 12 characters times 8 different widths of
-                // unicode block.
-                let frac = (96.0 * (limit as f32 - remaining as f32) / limit as f32) as i32;
-                let mut timebar = "◂".to_string();
-                for _ in 0 .. (frac / 8) {
-                    timebar.push_str("█");
-                }
-                if remaining > 0 {
-                    timebar.push_str(match frac % 8 {
-                        0 => " ",
-                        1 => "▏",
-                        2 => "▎",
-                        3 => "▍",
-                        4 => "▌",
-                        5 => "▋",
-                        6 => "▊",
-                        7 => "▉",
-                        _ => "",
-                    });
-                }
-                for _ in (frac / 8) .. 11 {
-                    timebar.push_str(" ");
-                }
-                timebar.push_str("▸");
+    nc::start_color();
+    nc::use_default_colors();
+}
 
-                (timebar, remaining == 0)
-            }
+fn print_colored(y: i32, x: i32, text: &str, color: i16) {
+    let colors = nc::has_colors();
+    if colors {
+        nc::attron(nc::COLOR_PAIR(color));
+    }
+    nc::mvprintw(y, x, text);
+    if colors {
+        nc::attroff(nc::COLOR_PAIR(color));
+    }
+}
 
-            let (timebar, over) = paint_gauge(&last_drop_time, 15000);
-            if over {
-                drop = true;
-            }
+fn logo() {
+    logo_for_level(0);
+}
 
-            let (mult_timebar, mult_over) = paint_gauge(&last_mult_time, 60000);
-            if mult_over && multiplier != 1 {
-                mult_drop = true;
-            }
+// The logo dresses up once the session has seen a good game.  Level 10
+// makes it bold, level 20 also gilds it.
+fn logo_for_level(level: u8) {
+    let mut grid = Grid::new(24, 4);
+    for &x in &[0, 22] {
+        grid.paint_slant(x, 2, 3, Slant::Fall, Pen::Thin);
+        grid.paint_slant(x, 4, 3, Slant::Rise, Pen::Thin);
+    }
+    let (rule, name) = if level >= 10 {
+        ("╺━━━━━━━━━━━━━━━╸", "━┫ G R I D - O ┣━")
+    } else {
+        ("╶─╼━━━━━━━━━━━╾─╴", "╶╼ G R I D - O ╾╴")
+    };
+    grid.paint_decoration(4, 2, rule);
+    grid.paint_decoration(4, 3, name);
+    grid.paint_decoration(4, 4, rule);
+    if level >= 20 {
+        // Yellow, like the centerpieces.
+        let (w, h) = (grid.w, grid.h);
+        grid.color_rect(0, 0, w, h, 3);
+    }
+    grid.render(0, 0, false);
+}
 
-            for p in &particles {
-                p.paint(&mut grid);
-            }
+// How long the menu waits before it starts the demo.
+const DEMO_IDLE_MS: i32 = 30000;
 
-            nc::erase();
-            grid.render(0, 0);
-            gridlet.render(grid.w + 1, 0);
-            nc::mvprintw(gridlet.h as i32 + 1, grid.w as i32 + 1, &timebar);
-            nc::mvprintw(gridlet.h as i32 + 2, grid.w as i32 + 1,
-                         &format!("Score: {}", score));
-            nc::mvprintw(gridlet.h as i32 + 3, grid.w as i32 + 1,
-                         &format!("Level: {}", level(score)));
-
-            nc::mvprintw(gridlet.h as i32 + 5, grid.w as i32 + 1, &mult_timebar);
-            nc::mvprintw(gridlet.h as i32 + 6, grid.w as i32 + 1,
-                         &format!("Multi: x{}", multiplier));
-            nc::refresh();
-        }
-
-        fn block_collides(block: &Block, bd: &Block, pg: &Block) -> bool {
-            block.collides_with(&bd) || block.collides_with(&pg)
-        }
-
-        fn try_move(moved: Block, blk: Block, bd: &Block, pg: &mut Block) -> Block {
-            if moved.intersects(bd) {
-                blk
-            } else if moved.collides_with(pg) {
-                let (moved2, pg2) = Block::collide(moved, pg);
-                if moved2.collides_with(&pg2) {
-                    blk
-                } else {
-                    *pg = pg2;
-                    moved2
-                }
-            } else {
-                moved
-            }
-        };
+// The difficulty is picked right in the menu, and remembered in the
+// config file.  Level is the best one reached this session, and only
+// decides the looks of the logo.
+fn menu(keys: &KeyBindings, config: &mut Config, level: u8) -> MenuAction {
+    let mut pos: i32 = 0;
 
-        nc::timeout(20);
-        match nc::getch() {
-            nc::KEY_LEFT => blk = try_move(blk.moved(-1, 0), blk, &bd, &mut pg),
-            nc::KEY_RIGHT => blk = try_move(blk.moved(1, 0), blk, &bd, &mut pg),
-            nc::KEY_UP => blk = try_move(blk.moved(0, -1), blk, &bd, &mut pg),
-            nc::KEY_DOWN => blk = try_move(blk.moved(0, 1), blk, &bd, &mut pg),
-            nc::KEY_BACKSPACE => {
-                let moved = next.moved_to(blk.x, blk.y);
-                if !block_collides(&moved, &bd, &pg) {
-                    next = blk.moved_to(1, 1);
-                    blk = moved;
-                }
-            },
+    let mut items = vec![("Play", MenuAction::Play),
+                         ("Difficulty", MenuAction::Difficulty),
+                         ("Help", MenuAction::Help),
+                         ("Scores", MenuAction::Scores),
+                         ("Stats", MenuAction::Stats),
+                         ("Options", MenuAction::Options),
+                         ("Quit", MenuAction::Quit)];
+    let saved = SavedGame::exists();
+    if saved {
+        items.insert(0, ("Continue", MenuAction::Continue));
+    }
 
-            n => match n as u8 as char {
-                '\t' => blk = try_move(blk.turned(), blk, &bd, &mut pg),
-                '\r' => {
-                    let grace = time::Duration::milliseconds(500);
-                    if time::SteadyTime::now() - last_drop_time > grace {
-                        drop = true;
-                    }
-                },
-                /*
-                ' ' => blk = Block::new_random(score).moved_to(2, 2),
-                '+' => score += 500,
-                '*' => multiplier += 1,
-                */
-                'q' => break,
-                'p' => {
-                    let pause_start = time::SteadyTime::now();
-                    nc::erase();
-                    nc::mvprintw(pgh as i32, 2 * pgw as i32 - 3, "Pause.");
-                    nc::timeout(-1);
-                    nc::getch();
-                    let now = time::SteadyTime::now();
-                    last_drop_time = last_drop_time + (now - pause_start);
-                    last_mult_time = last_mult_time + (now - pause_start);
-                },
-                _ => {
-                    /*
-                    nc::endwin();
-                    println!("{}", n);
-                    return
-                     */
-                },
+    loop {
+        nc::erase();
+        logo_for_level(level);
+        for i in 0..items.len() {
+            if i == pos as usize {
+                nc::mvprintw(i as i32 + 6, 1, "➤");
+            }
+            let &(text, action) = &items[i];
+            nc::mvprintw(i as i32 + 6, 3, text);
+            if let MenuAction::Difficulty = action {
+                nc::mvprintw(i as i32 + 6, 15, config.difficulty.name());
             }
         }
 
-        if blk.tiles.is_empty() || drop {
-            if blk.drop(&mut pg, &bd) {
-                last_drop_time = time::SteadyTime::now();
-                let (_, hits, dmult) = pg.explode();
-                let bonus = hits * multiplier;
-                score += bonus;
+        // Left alone, the menu starts the demo.
+        nc::timeout(DEMO_IDLE_MS);
+        let key = nc::getch();
+        match (keys.action(key), key) {
+            (_, nc::ERR) => return MenuAction::Demo,
+            (Some(Action::Up), _) | (_, nc::KEY_UP) => pos -= 1,
+            (Some(Action::Down), _) | (_, nc::KEY_DOWN) => pos += 1,
+            (_, n) => match n as u8 as char {
+                '\r' => match items[pos as usize].1 {
+                    MenuAction::Difficulty => next_difficulty(config),
+                    action => return action,
+                },
+                'd' => next_difficulty(config),
+                'p' => return MenuAction::Play,
+                'c' if saved => return MenuAction::Continue,
+                'h' => return MenuAction::Help,
+                's' => return MenuAction::Scores,
+                't' => return MenuAction::Stats,
+                'o' => return MenuAction::Options,
+                'q' => return MenuAction::Quit,
+                _ => {},
+            },
+        }
 
-                if dmult != 0 {
-                    if dmult < 0 {
-                        if -dmult as u32 >= multiplier {
-                            multiplier = 0
-                        } else {
-                            multiplier -= -dmult as u32;
-                        }
-                    } else {
-                        multiplier += dmult as u32;
-                    }
+        if pos < 0 {
+            pos = 0;
+        }
+        if pos >= items.len() as i32 {
+            pos = (items.len() - 1) as i32;
+        }
+    }
+}
 
-                    last_mult_time = time::SteadyTime::now();
-                }
+// Play the saved game.  It's gone once continued, so that it can't be
+// played out more than once.  A save that can't be read is of no use
+// either, but one for a playground of another size is kept until
+// grido is started with that size again.
+fn continue_game(config: &mut Config, best: &mut u32, args: &Args) -> AfterGame {
+    let problem = match SavedGame::load() {
+        Ok(ref saved) if (saved.width, saved.height) != (args.width, args.height) =>
+            format!("The saved game needs --width {} --height {}.",
+                    saved.width, saved.height),
+        Ok(saved) => {
+            SavedGame::remove();
+            return play(config, best, args, Mode::Continue(saved));
+        },
+        Err(msg) => {
+            SavedGame::remove();
+            format!("Couldn't continue: {}", msg)
+        },
+    };
 
-                if bonus > 0 {
-                    particles.push(Particle::new(4. * blk.x as f32, 2. * blk.y as f32,
-                                                 format!("{}", bonus), 5000));
-                }
+    nc::erase();
+    nc::mvprintw(1, 1, &problem);
+    nc::mvprintw(3, 1, "Press any key.");
+    nc::timeout(-1);
+    nc::getch();
+    AfterGame::Menu
+}
 
-                if dmult > 0 {
-                    particles.push(Particle::new(4. * blk.x as f32, 1. + 2. * blk.y as f32,
-                                                 format!("+x{}", dmult), 5000));
-                } else if dmult < 0 {
-                    particles.push(Particle::new(4. * blk.x as f32, 1. + 2. * blk.y as f32,
-                                                 format!("-x{}", -dmult), 5000));
-                }
+// Keep starting new games for as long as the player asks for one at the
+// end of the last.
+fn play_on(config: &mut Config, best: &mut u32, args: &Args, mut after: AfterGame) {
+    while after == AfterGame::Restart {
+        after = play(config, best, args, Mode::Live);
+    }
+}
 
-                blk = next.moved(1, 1);
-                next = Block::new_random(score).moved_to(1, 1);
-                if block_collides(&blk, &bd, &pg) {
-                    break;
-                }
-            }
-        }
+// Ask whether to pick up a game that was cut short, see
+// SavedGame::autosave.
+fn offer_recovery() -> bool {
+    nc::erase();
+    logo();
+    nc::mvprintw(6, 1, "The last game didn't end properly.");
+    nc::mvprintw(7, 1, "Pick it up where it was? y/n");
+    nc::timeout(-1);
+    nc::getch() == 'y' as i32
+}
 
-        if mult_drop {
-            multiplier = if multiplier > 1 { multiplier - 1 }
-            		 else { multiplier + 1 };
-            last_mult_time = time::SteadyTime::now();
-        } else if multiplier == 1 {
-            last_mult_time = time::SteadyTime::now();
-        }
-    }
+fn next_difficulty(config: &mut Config) {
+    config.difficulty = config.difficulty.next();
+    // The menu works all the same if this doesn't stick.
+    let _ = config.save();
 }
 
-#[derive(Copy, Clone)]
-enum MenuAction {
-    Play,
-    Help,
-    Quit,
+fn confirm(y: i32, question: &str) -> bool {
+    nc::mvprintw(y, 1, &format!("{} y/n", question));
+    nc::timeout(-1);
+    nc::getch() == 'y' as i32
 }
 
-fn logo() {
-    nc::mvprintw(2, 1, "╶─╼━━━━━━━━━━━╾─╴");
-    nc::mvprintw(3, 1, "╶╼ G R I D - O ╾╴");
-    nc::mvprintw(4, 1, "╶─╼━━━━━━━━━━━╾─╴");
+// A line of the options screen: what it's called, the value as
+// shown, and how selecting it changes the value.
+type Setting = (&'static str, fn(&Config) -> String, fn(&mut Config));
+
+// Put the defaults back, if the player is sure.
+fn reset_options(config: &mut Config, y: i32) {
+    if confirm(y, "Reset all settings to defaults?") {
+        if let Err(err) = config.reset() {
+            nc::mvprintw(y + 1, 1, &format!("Couldn't save settings: {}", err));
+            nc::getch();
+        }
+    }
 }
 
-fn menu() -> MenuAction {
+fn options(config: &mut Config) {
+    let settings: &[Setting]
+        = &[("Popups", |c| c.popups.name().to_string(),
+             |c| c.popups = c.popups.next()),
+            ("Stalling", |c| c.stalling.name().to_string(),
+             |c| c.stalling = c.stalling.next()),
+            ("Mercy", |c| on_off(c.mercy).to_string(),
+             |c| c.mercy = !c.mercy),
+            ("Detonation", |c| c.detonation.name().to_string(),
+             |c| c.detonation = c.detonation.next()),
+            ("Score format", |c| c.score_format.name().to_string(),
+             |c| c.score_format = c.score_format.next()),
+            ("Hints", |c| on_off(c.hints).to_string(),
+             |c| c.hints = !c.hints),
+            ("Score breakdown", |c| on_off(c.breakdown).to_string(),
+             |c| c.breakdown = !c.breakdown),
+            ("Previews", |c| c.previews.to_string(),
+             |c| c.previews = c.previews % MAX_PREVIEWS + 1),
+            ("Rounded tiles", |c| on_off(c.rounded).to_string(),
+             |c| c.rounded = !c.rounded),
+            ("Distinct tiles", |c| on_off(c.distinct).to_string(),
+             |c| c.distinct = !c.distinct),
+            ("Keys", |c| c.keys.name().to_string(),
+             |c| c.keys = c.keys.next()),
+            ("Level curve", |c| c.level_curve.name().to_string(),
+             |c| c.level_curve = c.level_curve.next()),
+            ("Drop animation", |c| on_off(c.drop_animation).to_string(),
+             |c| c.drop_animation = !c.drop_animation),
+            ("Reduce motion", |c| on_off(c.reduce_motion).to_string(),
+             |c| c.reduce_motion = !c.reduce_motion),
+            ("Drop guide", |c| on_off(c.guide).to_string(),
+             |c| c.guide = !c.guide),
+            ("Theme", |c| c.theme.name().to_string(),
+             |c| c.theme = c.theme.next()),
+            ("Background", |c| c.background.name().to_string(),
+             |c| c.background = c.background.next()),
+            ("Side panel", |c| c.hud.name().to_string(),
+             |c| c.hud = c.hud.next()),
+            ("Blind", |c| on_off(c.blind).to_string(),
+             |c| c.blind = !c.blind),
+            ("Game mode", |c| c.game_mode.name().to_string(),
+             |c| c.game_mode = c.game_mode.next()),
+            ("Frame", |c| on_off(c.frame).to_string(),
+             |c| c.frame = !c.frame),
+            ("Drop time", |c| format!("{} s", c.drop_time),
+             |c| c.drop_time = c.drop_time % MAX_DROP_TIME + MIN_DROP_TIME),
+            ("Drop grace", |c| format!("{} ms", c.drop_grace),
+             |c| c.drop_grace = (c.drop_grace + 250) % (MAX_DROP_GRACE_MS + 250)),
+            ("Mirrored", |c| on_off(c.mirrored).to_string(),
+             |c| c.mirrored = !c.mirrored),
+            ("Handicap", |c| match c.handicap {
+                0 => "off".to_string(),
+                1 => "1 row".to_string(),
+                n => format!("{} rows", n),
+             },
+             |c| c.handicap = (c.handicap + 1) % (MAX_HANDICAP + 1)),
+            ("Randomizer", |c| c.randomizer.name().to_string(),
+             |c| c.randomizer = c.randomizer.next()),
+            ("Autosave", |c| on_off(c.autosave).to_string(),
+             |c| c.autosave = !c.autosave)];
+
+    let reset = settings.len() as i32;
+    let back = reset + 1;
     let mut pos: i32 = 0;
 
-    let items = [("Play", MenuAction::Play),
-                 ("Help", MenuAction::Help),
-                 ("Quit", MenuAction::Quit)];
+    // The list scrolls if the terminal is too short for all of it.
+    // Two lines below it are kept for questions and errors.
+    let mut top: i32 = 0;
+    let mut below;
 
     loop {
+        let (_, lines) = terminal_size();
+        let rows = cmp::max(lines - 6 - 2, 1);
+        if pos < top {
+            top = pos;
+        }
+        if pos >= top + rows {
+            top = pos - rows + 1;
+        }
+        below = 6 + cmp::min(rows, back + 1);
+
         nc::erase();
         logo();
-        for i in 0..items.len() {
-            if i == pos as usize {
-                nc::mvprintw(i as i32 + 6, 1, "➤");
+        for i in top..cmp::min(top + rows, back + 1) {
+            let y = i - top + 6;
+            if i < reset {
+                let (text, value, _) = settings[i as usize];
+                nc::mvprintw(y, 3, text);
+                nc::mvprintw(y, 20, &value(config));
+            } else if i == reset {
+                nc::mvprintw(y, 3, "Reset to defaults");
+            } else {
+                nc::mvprintw(y, 3, "Back");
             }
-            let &(text, _) = &items[i];
-            nc::mvprintw(i as i32 + 6, 3, text);
         }
+        nc::mvprintw(pos - top + 6, 1, "➤");
 
         nc::timeout(-1);
         match nc::getch() {
             nc::KEY_UP => pos -= 1,
             nc::KEY_DOWN => pos += 1,
             n => match n as u8 as char {
-                '\r' => {
-                    let &(_, action) = &items[pos as usize];
-                    return action;
-                },
-                'p' => return MenuAction::Play,
-                'h' => return MenuAction::Help,
-                'q' => return MenuAction::Quit,
+                '\r' if pos < reset => (settings[pos as usize].2)(config),
+                '\r' if pos == reset => reset_options(config, below + 1),
+                '\r' | 'q' => break,
                 _ => {},
             },
         }
@@ -1291,13 +4388,18 @@ fn menu() -> MenuAction {
         if pos < 0 {
             pos = 0;
         }
-        if pos >= items.len() as i32 {
-            pos = (items.len() - 1) as i32;
+        if pos > back {
+            pos = back;
         }
     }
+
+    if let Err(err) = config.save() {
+        nc::mvprintw(below + 1, 1, &format!("Couldn't save settings: {}", err));
+        nc::getch();
+    }
 }
 
-fn help() {
+fn help(look: Look) {
     nc::erase();
     logo();
 
@@ -1305,44 +4407,25 @@ fn help() {
 
     let mut y = 3;
     let mut x = 1;
-    for &(tts, descr)
-        in &[(&vec![TileType::Plain(0)],
-              "Plain tiles.  When organized\ninto a 3x3, explode and\n\
-               disappear.  1 point."),
-             (&vec![TileType::Plain(1), TileType::Plain(3)],
-              "Shield tiles.  When exploded,\ndecrease the number, eventually\n\
-               change to plain.  n+1 points."),
-             (&vec![TileType::Centerpiece(1), TileType::Centerpiece(3)],
-              "Centerpiece.  Only explode\nwhen 3x3 has a centerpiece\n\
-               in the center.  10*n points."),
-             (&vec![TileType::Whopper(1), TileType::Whopper(3)],
-              "Whopper.  Like centerpiece\nbut only explodes 5x5.  When\n\
-               exploded, changes to c-piece\nwith the same number.\n\
-               30 points."),
-             (&vec![TileType::Picker],
-              "Picker.  Doesn't explode.\nAllows picking other tiles."),
-             (&vec![TileType::Killer(1), TileType::Killer(3)],
-              "Killer.  Kills tiles that\nit touches.  On drop,\nchanges to plain."),
-             (&vec![TileType::Permanent],
-              "Permanent.\nNever explodes.\nKill them!"),
-             (&vec![TileType::Plus, TileType::Minus],
-              "Plus, Minus.  When exploded,\nchange the multiplier.\n\
-               1 point."),
-             (&vec![TileType::Flask(LiquidType::Glue),
-                    TileType::Flask(LiquidType::Acid)],
-              "Flask with Glue and Acid.\nSpill contents around\n\
-               when exploded.  1 point.")] {
+    for tts in &[vec![TileType::Plain(0)],
+                 vec![TileType::Plain(1), TileType::Plain(3)],
+                 vec![TileType::Centerpiece(1), TileType::Centerpiece(3)],
+                 vec![TileType::Whopper(1), TileType::Whopper(3)],
+                 vec![TileType::Picker],
+                 vec![TileType::Killer(1), TileType::Killer(3)],
+                 vec![TileType::Permanent],
+                 vec![TileType::Plus, TileType::Minus],
+                 vec![TileType::Flask(LiquidType::Glue),
+                      TileType::Flask(LiquidType::Acid)]] {
         {
             let mut blk = Block::new_at(x, y);
             for i in 0..tts.len() {
                 blk.tiles.push((-(tts.len() as i16) + i as i16 + 1, 0, tts[i]));
             }
-            blk.paint(&mut grid);
+            blk.paint(&mut grid, look);
 
-            let mut dy = 0;
-            for k in descr.split("\n") {
-                grid.paint_decoration(4 * (x + 1) + 2, 2 * y + dy, k);
-                dy += 1
+            for (dy, k) in tts[0].description().split("\n").enumerate() {
+                grid.paint_decoration(4 * (x + 1) + 2, 2 * y + dy as i16, k);
             }
         }
         y += 2;
@@ -1352,7 +4435,7 @@ fn help() {
         }
     }
 
-    grid.render(0, 0);
+    grid.render(0, 0, look.ascii);
 
     nc::getch();
 
@@ -1360,32 +4443,765 @@ fn help() {
     logo();
     nc::mvprintw(6, 1,  "⬅⬆⬇➡  Arrows: move current block around the playground.");
     nc::mvprintw(7, 1,  "   ↲  Enter: drop the block.");
-    nc::mvprintw(8, 1,  "   ⇰  Tab: rotate the block.");
-    nc::mvprintw(9, 1,  "   ⇦  Backspace: swap current block with the next block.");
-    nc::mvprintw(12, 1, "   p  Pause game.");
-    nc::mvprintw(13, 1, "   q  Quit game--go back to the menu.");
+    nc::mvprintw(8, 1,  "   ␣  Space: move the block down as far as it goes and drop it.");
+    nc::mvprintw(9, 1,  "   ⇰  Tab: rotate the block.");
+    nc::mvprintw(10, 1, "   ⇤  Shift-Tab, z: rotate the other way.");
+    nc::mvprintw(11, 1, " h v  Flip the block left to right, top to bottom.");
+    nc::mvprintw(12, 1, "   ⇦  Backspace: swap current block with the next block.");
+    nc::mvprintw(13, 1, "   x  Detonate ready tiles (with manual detonation).");
+    nc::mvprintw(14, 1, "   u  Undo the last drop.");
+    nc::mvprintw(15, 1, "   i  Inspect tiles: the arrows move a cursor, read about the tile under it.");
+    nc::mvprintw(16, 1, "   f  Hold to run the drop gauge faster.");
+    nc::mvprintw(17, 1, "   p  Pause game.");
+    nc::mvprintw(18, 1, "   q  Quit game--go back to the menu.");
 
     nc::getch();
 }
 
+#[derive(Clone)]
+struct Args {
+    ai_bench: Option<u32>,
+    tile_stats: Option<u32>,
+    seed: Option<u64>,
+    width: i16,
+    height: i16,
+    ascii: bool,
+    plain: bool,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    simulate: Option<String>,
+    practice: Option<PathBuf>,
+    dump_frames: Option<PathBuf>,
+    fps: i32,
+    debug: bool,
+    sound: bool,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut ret = Args {ai_bench: None, tile_stats: None, seed: None,
+                        width: PG_WIDTH, height: PG_HEIGHT, ascii: false,
+                        plain: false, record: None, replay: None, simulate: None,
+                        practice: None, dump_frames: None, fps: FPS,
+                        debug: false, sound: false};
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ai-bench" => {
+                let n = args.next().ok_or("--ai-bench needs a number of games")?;
+                ret.ai_bench = Some(n.parse().map_err(|_| format!("bad number of games: {}", n))?);
+            },
+            "--stats" => {
+                let n = args.next().ok_or("--stats needs a score")?;
+                ret.tile_stats = Some(n.parse().map_err(|_| format!("bad score: {}", n))?);
+            },
+            "--seed" => {
+                let n = args.next().ok_or("--seed needs a number")?;
+                ret.seed = Some(n.parse().map_err(|_| format!("bad seed: {}", n))?);
+            },
+            "--ascii" => ret.ascii = true,
+            "--plain" => ret.plain = true,
+            "--debug" => ret.debug = true,
+            "--sound" => ret.sound = true,
+            "--simulate" => {
+                let path = args.next().ok_or("--simulate needs a map file")?;
+                ret.simulate = Some(path);
+            },
+            "--record" => {
+                let path = args.next().ok_or("--record needs a file name")?;
+                ret.record = Some(PathBuf::from(path));
+            },
+            "--replay" => {
+                let path = args.next().ok_or("--replay needs a file name")?;
+                ret.replay = Some(PathBuf::from(path));
+            },
+            "--practice" => {
+                let path = args.next().ok_or("--practice needs a file name")?;
+                ret.practice = Some(PathBuf::from(path));
+            },
+            "--dump-frames" => {
+                let path = args.next().ok_or("--dump-frames needs a directory")?;
+                ret.dump_frames = Some(PathBuf::from(path));
+            },
+            "--fps" => {
+                let n = args.next().ok_or("--fps needs a number")?;
+                ret.fps = match n.parse() {
+                    Ok(fps) if (MIN_FPS..=MAX_FPS).contains(&fps) => fps,
+                    _ => return Err(format!("bad fps: {}, expected {} to {}",
+                                            n, MIN_FPS, MAX_FPS)),
+                };
+            },
+            "--width" | "--height" => {
+                let n = args.next().ok_or(format!("{} needs a number", arg))?;
+                let size = match n.parse() {
+                    Ok(size) if (PG_MIN_SIZE..=PG_MAX_SIZE).contains(&size) => size,
+                    _ => return Err(format!("bad {}: {}, expected {} to {}",
+                                            &arg[2..], n, PG_MIN_SIZE, PG_MAX_SIZE)),
+                };
+                if arg == "--width" {
+                    ret.width = size;
+                } else {
+                    ret.height = size;
+                }
+            },
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    Ok(ret)
+}
+
 fn main() {
+    let mut args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("grido: {}", msg);
+            process::exit(1);
+        },
+    };
+
+    if let Some(games) = args.ai_bench {
+        ai_bench(games);
+        return;
+    }
+
+    if let Some(score) = args.tile_stats {
+        tile_stats(score, Config::load().level_curve, args.seed);
+        return;
+    }
+
+    if let Some(ref path) = args.simulate {
+        if let Err(msg) = simulate(path) {
+            eprintln!("grido: {}", msg);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(ref dir) = args.dump_frames {
+        if let Err(err) = fs::create_dir_all(dir) {
+            eprintln!("grido: couldn't create {}: {}", dir.display(), err);
+            process::exit(1);
+        }
+    }
+
+    let mut config = Config::load();
+    let mut best = 0;
+
+    // A replay is played with the settings it was recorded with.
+    let mut replay = None;
+    if let Some(ref path) = args.replay.clone() {
+        let rec = match Recording::load(path) {
+            Ok(rec) => rec,
+            Err(msg) => {
+                eprintln!("grido: {}", msg);
+                process::exit(1);
+            },
+        };
+
+        config = rec.config;
+        // Keep the replay from touching the config file.
+        config.swapped = true;
+        args.seed = Some(rec.seed);
+        args.width = rec.width;
+        args.height = rec.height;
+        args.record = None;
+        replay = Some(rec.events);
+    }
+
+    let practice = match args.practice {
+        Some(ref path) => match Scripted::load(path) {
+            Ok(script) => Some(script),
+            Err(msg) => {
+                eprintln!("grido: {}", msg);
+                process::exit(1);
+            },
+        },
+        None => None,
+    };
+
+    // A broken theme file shouldn't keep anyone from playing.
+    let theme = Theme::load(config.theme).unwrap_or_else(|msg| {
+        eprintln!("grido: {}, using the default theme", msg);
+        Theme::builtin(ThemeName::Default)
+    });
+
     nc::setlocale(nc::LcCategory::all, "");
 
     nc::initscr();
+
+    // The terminal size is only known now.  Give the screen back
+    // before complaining, so that the message stays visible.
+    let layout = config.hud.resolve(args.width, args.height);
+    let (cols, lines) = screen_size(args.width, args.height, layout);
+    let (maxx, maxy) = terminal_size();
+    if maxx < cols || maxy < lines {
+        nc::endwin();
+        eprintln!("grido: a {}x{} playground needs a {}x{} terminal, this one is {}x{}",
+                  args.width, args.height, cols, lines, maxx, maxy);
+        process::exit(1);
+    }
+
     nc::keypad(unsafe {nc::stdscr}, true);
     nc::nonl();
     nc::cbreak();
     nc::raw();
     nc::noecho();
     nc::curs_set(nc::CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+    init_colors();
+    theme.apply();
+
+    if let Some(events) = replay {
+        play(&mut config, &mut best, &args, Mode::Replay(events));
+        nc::endwin();
+        return;
+    }
+
+    if let Some(script) = practice {
+        play(&mut config, &mut best, &args, Mode::Practice(script));
+        nc::endwin();
+        return;
+    }
+
+    if SavedGame::recoverable() && offer_recovery() {
+        let after = match SavedGame::recover() {
+            Ok(()) => continue_game(&mut config, &mut best, &args),
+            Err(_) => AfterGame::Menu,
+        };
+        play_on(&mut config, &mut best, &args, after);
+    }
+    SavedGame::remove_autosave();
 
     loop {
-        match menu() {
-            MenuAction::Play => play(),
-            MenuAction::Help => help(),
+        let level = config.level_curve.level(best);
+        let action = menu(&KeyBindings::load(config.keys), &mut config, level);
+        match action {
+            MenuAction::Play => {
+                let after = play(&mut config, &mut best, &args, Mode::Live);
+                play_on(&mut config, &mut best, &args, after);
+            },
+            MenuAction::Continue => {
+                let after = continue_game(&mut config, &mut best, &args);
+                play_on(&mut config, &mut best, &args, after);
+            },
+            MenuAction::Demo => {
+                play(&mut config, &mut best, &args, Mode::Demo);
+            },
+            // The menu changes it by itself.
+            MenuAction::Difficulty => {},
+            MenuAction::Help => help(config.look(&args)),
+            MenuAction::Scores => scores(config.score_format),
+            MenuAction::Stats => lifetime_stats(config.score_format),
+            MenuAction::Options => {
+                options(&mut config);
+                match Theme::load(config.theme) {
+                    Ok(theme) => theme.apply(),
+                    Err(msg) => {
+                        nc::erase();
+                        nc::mvprintw(1, 1, &msg);
+                        nc::mvprintw(2, 1, "Using the default theme.");
+                        nc::mvprintw(4, 1, "Press any key.");
+                        nc::timeout(-1);
+                        nc::getch();
+                        Theme::builtin(ThemeName::Default).apply();
+                    },
+                }
+            },
             MenuAction::Quit => break,
         }
     }
 
     nc::endwin();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A game on the default playground that deals the given blocks
+    // over and over, with nothing on the terminal.
+    fn game(blocks: Vec<Block>) -> (GameState, Controls, Config, Feed) {
+        let config = Config::default();
+        let feed = Feed::replay(VecDeque::new());
+        let source = Box::new(Scripted {blocks, pos: 0});
+        let state = GameState::new(&config, PG_WIDTH, PG_HEIGHT, 0, source,
+                                   Bell {muted: true}, feed.now());
+        let look = Look {outline: Pen::Thik, ascii: false, plain: false, distinct: false};
+        let keys = KeyBindings::preset(KeyPreset::Arrows);
+        let ctl = Controls {
+            key_hint: keys.hint(false), keys, look,
+            frame_ms: 0, debug: false, input: VecDeque::new(),
+            demo_target: None, demo_steps: 0, demo_time: feed.now(),
+            show_keys: false, overlay: false, frame_time: time::Duration::zero(),
+            inspect: None, dump_frames: None, frames: 0,
+        };
+        (state, ctl, config, feed)
+    }
+
+    fn domino() -> Block {
+        Block::from_map("**\n").unwrap()
+    }
+
+    #[test]
+    fn burst_of_keys_applies_in_order() {
+        let (mut state, mut ctl, mut config, mut feed) = game(vec![domino()]);
+        let start = state.blk.clone();
+        // Past the drop grace.
+        state.dropped_at = feed.now() - time::Duration::seconds(1);
+        feed.keys = vec![nc::KEY_RIGHT, nc::KEY_RIGHT, nc::KEY_DOWN, '\t' as i32,
+                         '\r' as i32, nc::KEY_LEFT];
+
+        let input = state.handle_input(&mut ctl, &mut feed, &mut config, false).ok().unwrap();
+        assert!(input.drop);
+        assert_eq!(state.blk.tiles, start.moved(2, 1).turned().tiles);
+        assert_eq!((state.blk.x, state.blk.y), (start.x + 2, start.y + 1));
+
+        // What came after the drop waits for the next frame.
+        assert_eq!(ctl.input, vec![nc::KEY_LEFT]);
+    }
+
+    #[test]
+    fn mercy_block_is_plain() {
+        let (mut state, _, mut config, _) = game(vec![Block::from_map("XX\n").unwrap()]);
+        config.mercy = true;
+
+        // Permanent tiles everywhere but the corner where new blocks
+        // appear, so that nothing explodes.
+        for y in 1..PG_HEIGHT - 1 {
+            for x in 1..PG_WIDTH - 1 {
+                if x > 4 || y > 4 {
+                    state.pg.tiles.push((x, y, TileType::Permanent));
+                }
+            }
+        }
+        let queued = state.queue[0].clone();
+        state.spawn(&config);
+
+        assert!(state.mercy);
+        assert!(!state.blk.tiles.is_empty());
+        assert!(state.blk.tiles.iter().all(|&(_, _, tt)| tt == TileType::Plain(0)));
+        assert_eq!(state.queue[0].tiles, queued.tiles);
+    }
+
+    #[test]
+    fn score_formats() {
+        let cases: &[(u32, &str, &str, &str)] = &[
+            (0, "0", "0", "0"),
+            (999, "999", "999", "999"),
+            (1000, "1000", "1,000", "1.0k"),
+            (12_345, "12345", "12,345", "12.3k"),
+            (99_999, "99999", "99,999", "99.9k"),
+            (100_000, "100000", "100,000", "100k"),
+            (999_999, "999999", "999,999", "999k"),
+            (1_234_567, "1234567", "1,234,567", "1.2M"),
+            (4_294_967_295, "4294967295", "4,294,967,295", "4294M"),
+        ];
+        for &(score, raw, grouped, short) in cases {
+            assert_eq!(ScoreFormat::Raw.format(score), raw);
+            assert_eq!(ScoreFormat::Grouped.format(score), grouped);
+            assert_eq!(ScoreFormat::Short.format(score), short);
+        }
+    }
+
+    // A 3x3 with a clock that a domino at 6, 7 completes.
+    fn ready_board() -> Block {
+        Block::from_map("................\n\
+                         ................\n\
+                         ................\n\
+                         ................\n\
+                         ................\n\
+                         .....***........\n\
+                         .....*T*1.......\n\
+                         .....*..........\n").unwrap()
+    }
+
+    #[test]
+    fn simulate_drop_plays_like_the_game() {
+        let (mut state, mut ctl, config, feed) = game(vec![domino()]);
+        state.pg = ready_board();
+        state.blk = domino().moved_to(6, 7);
+        let outcome = simulate_drop(&state.pg, &state.blk, &state.bd).unwrap();
+
+        let now = feed.now();
+        let input = Input {drop: true, .. Input::default()};
+        assert!(state.step_simulation(input, false, &mut ctl, &config, now).is_none());
+
+        assert_eq!(outcome.hits, 9);
+        assert_eq!(outcome.time, CLOCK_MS);
+        assert_eq!(state.pg.tiles, outcome.pg.tiles);
+        assert_eq!(state.score, outcome.hits);
+        assert_eq!(state.drop_left(now), state.drop_interval() + outcome.time);
+    }
+
+    #[test]
+    fn level_curves() {
+        let cases: &[(LevelCurve, &[(u32, u8)])] = &[
+            (LevelCurve::Quadratic, &[(0, 0), (1, 1), (100, 1), (101, 2), (300, 2),
+                                      (301, 3), (600, 3), (601, 4)]),
+            (LevelCurve::Steep, &[(0, 0), (1, 1), (150, 1), (151, 2), (450, 2), (451, 3)]),
+            (LevelCurve::Linear, &[(0, 0), (1, 1), (300, 1), (301, 2), (600, 2), (601, 3)]),
+        ];
+        for &(curve, levels) in cases {
+            for &(score, lvl) in levels {
+                assert_eq!(curve.level(score), lvl, "{:?} at {}", curve, score);
+            }
+            assert!(curve.level(u32::MAX) > 0);
+        }
+    }
+
+    #[test]
+    fn undo_takes_back_the_explosion() {
+        let (mut state, mut ctl, mut config, mut feed) = game(vec![domino()]);
+        state.pg = ready_board();
+        state.blk = domino().moved_to(6, 7);
+        let (pg, blk) = (state.pg.clone(), state.blk.clone());
+
+        let now = feed.now();
+        let input = Input {drop: true, .. Input::default()};
+        assert!(state.step_simulation(input, false, &mut ctl, &config, now).is_none());
+        assert!(state.score > 0 && state.chain == 1 && !state.stats.exploded.is_empty());
+
+        feed.keys = vec!['u' as i32];
+        state.handle_input(&mut ctl, &mut feed, &mut config, false).ok().unwrap();
+        assert_eq!(state.pg.tiles, pg.tiles);
+        assert_eq!(state.blk.tiles, blk.tiles);
+        assert_eq!((state.score, state.chain), (0, 0));
+        assert!(state.stats.exploded.is_empty());
+        assert!(state.undo.is_none());
+    }
+
+    #[test]
+    fn keys_file() {
+        let mut keys = KeyBindings::preset(KeyPreset::Arrows);
+        keys.parse("# comment\n\
+                    rotate = shift-tab e\n\
+                    swap=backspace\n\
+                    bogus = x\n\
+                    drop = enter nonsense\n\
+                    pause =\n");
+        assert_eq!(keys.action(nc::KEY_BTAB), Some(Action::Rotate));
+        assert_eq!(keys.action('e' as i32), Some(Action::Rotate));
+        assert_eq!(keys.action('\t' as i32), None);
+        assert_eq!(keys.action(nc::KEY_BACKSPACE), Some(Action::Swap));
+        assert_eq!(keys.action('\r' as i32), Some(Action::Drop));
+        assert_eq!(keys.action('p' as i32), Some(Action::Pause));
+    }
+
+    #[test]
+    fn key_hint_names() {
+        let keys = KeyBindings {keys: vec![(nc::KEY_BTAB, Action::Swap), ('w' as i32, Action::Up),
+                                           (nc::KEY_BACKSPACE, Action::Quit)]};
+        assert_eq!(keys.hint(true), "w move  s-tab swap  bksp quit  ? hide");
+        assert_eq!(keys.hint(false), "w move  ⇤ swap  ⌫ quit  ? hide");
+    }
+
+    #[test]
+    fn multiplier_stays_positive() {
+        assert_eq!(apply_dmult(1, 2), 3);
+        assert_eq!(apply_dmult(5, -2), 3);
+        assert_eq!(apply_dmult(3, -3), MIN_MULTIPLIER);
+        assert_eq!(apply_dmult(3, -40), MIN_MULTIPLIER);
+        assert_eq!(apply_dmult(MIN_MULTIPLIER, -1), MIN_MULTIPLIER);
+
+        // A square of Minus tiles takes more off than there is.
+        let (state, _, _, _) = game(vec![domino()]);
+        let pg = Block::from_map("................\n\
+                                  ................\n\
+                                  ................\n\
+                                  ................\n\
+                                  ................\n\
+                                  .....---........\n\
+                                  .....---........\n\
+                                  .....-..........\n").unwrap();
+        let outcome = simulate_drop(&pg, &domino().moved_to(6, 7), &state.bd).unwrap();
+        assert_eq!(outcome.dmult, -7);
+        assert_eq!(apply_dmult(2, outcome.dmult), MIN_MULTIPLIER);
+    }
+
+    #[test]
+    fn clock_keeps_the_drop_key() {
+        let (mut state, mut ctl, mut config, mut feed) = game(vec![domino(), domino()]);
+        state.pg = ready_board();
+        state.blk = domino().moved_to(6, 7);
+        let input = Input {drop: true, .. Input::default()};
+        assert!(state.step_simulation(input, false, &mut ctl, &config, feed.now()).is_none());
+        assert!(state.drop_left(feed.now()) > state.drop_interval());
+
+        // The clock filled the gauge, yet the drop grace is over as
+        // soon as it would be without it.
+        feed.now = feed.now + time::Duration::milliseconds(config.drop_grace as i64 + 1);
+        feed.keys = vec!['\r' as i32];
+        let input = state.handle_input(&mut ctl, &mut feed, &mut config, false).ok().unwrap();
+        assert!(input.drop);
+    }
+
+    #[test]
+    fn bag_deals_every_shape() {
+        fn shape_of(blk: &Block) -> usize {
+            let mut fields: Vec<_> = blk.tiles.iter().map(|&(x, y, _)| (x, y)).collect();
+            fields.sort();
+            (0..SHAPES).find(|&n| {
+                let mut shape = Block::shape(n).to_vec();
+                shape.sort();
+                shape == fields
+            }).unwrap()
+        }
+
+        let mut rng: StdRng = SeedableRng::from_seed(&[7][..]);
+        let mut bag = Bag::new();
+        for round in 0..3 {
+            let mut shapes: Vec<_> = (0..SHAPES).map(|_| shape_of(&bag.next(3, &mut rng)))
+                .collect();
+            shapes.sort();
+            assert_eq!(shapes, (0..SHAPES).collect::<Vec<_>>(), "round {}", round);
+        }
+
+        // Rewinding starts a new round.
+        bag.next(3, &mut rng);
+        bag.rewind();
+        assert!(bag.shapes.is_empty());
+    }
+
+    #[test]
+    fn popup_faces() {
+        let mut config = Config::default();
+        config.parse("popup_template = <{}>\n\
+                      popup_template = no value\n");
+        let cases = [(PopupStyle::Plain, "15", "+x2", "-x1"),
+                     (PopupStyle::Framed, "«+15»", "▲x2", "▼x1"),
+                     (PopupStyle::Custom, "<15>", "<+x2>", "<-x1>")];
+        for &(style, score, up, down) in &cases {
+            config.popups = style;
+            let template = &config.popup_template;
+            assert_eq!(config.popups.score(15, template), score);
+            assert_eq!(config.popups.multiplier(2, template), up);
+            assert_eq!(config.popups.multiplier(-1, template), down);
+        }
+    }
+
+    #[test]
+    fn reset_config() {
+        // Somewhere the saved config can't get in the player's way.
+        let dir = env::temp_dir().join(format!("grido-test-{}", process::id()));
+        env::set_var("XDG_CONFIG_HOME", &dir);
+
+        let mut config = Config {popups: PopupStyle::Framed, previews: 1, keys: KeyPreset::Wasd,
+                                 swapped: true, .. Config::default()};
+        assert!(config != Config::default());
+
+        config.reset().unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(Config::load(), Config::default());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stall_penalties() {
+        let (mut state, mut ctl, mut config, feed) = game(vec![domino()]);
+        let now = feed.now();
+
+        // Garbage lands somewhere on the empty board.
+        config.stalling = StallPenalty::Garbage;
+        state.stalls = STALL_LIMIT;
+        assert!(state.step_simulation(Input::default(), false, &mut ctl, &config, now)
+                .is_none());
+        assert_eq!(state.pg.tiles.len(), 1);
+        assert_eq!(state.pg.tiles[0].2, TileType::Plain(0));
+        assert_eq!(state.stalls, 0);
+
+        // Hurrying takes from the drop gauge instead.
+        config.stalling = StallPenalty::Hurry;
+        state.stalls = STALL_LIMIT;
+        let left = state.drop_left(now);
+        assert!(state.step_simulation(Input::default(), false, &mut ctl, &config, now)
+                .is_none());
+        assert_eq!(state.drop_left(now), left - STALL_HURRY_MS);
+        assert_eq!(state.pg.tiles.len(), 1);
+        assert_eq!(state.stalls, 0);
+    }
+
+    #[test]
+    fn manual_detonation() {
+        let (mut state, mut ctl, mut config, mut feed) = game(vec![domino(); 3]);
+        config.detonation = Detonation::Manual;
+
+        // Two squares that a domino each makes ready.
+        state.pg = Block::from_map("................\n\
+                                    ................\n\
+                                    ................\n\
+                                    ................\n\
+                                    ................\n\
+                                    .....***...***..\n\
+                                    .....***...***..\n\
+                                    .....*.....*....\n").unwrap();
+        let now = feed.now();
+        for &x in &[6, 12] {
+            state.blk = domino().moved_to(x, 7);
+            let input = Input {drop: true, .. Input::default()};
+            assert!(state.step_simulation(input, false, &mut ctl, &config, now).is_none());
+        }
+        assert_eq!(state.pg.tiles.len(), 18);
+        assert_eq!(state.score, 0);
+
+        feed.keys = vec!['x' as i32];
+        state.handle_input(&mut ctl, &mut feed, &mut config, false).ok().unwrap();
+        assert!(state.pg.tiles.is_empty());
+        assert!(state.score > 0);
+    }
+
+    #[test]
+    fn manual_detonation_on_full_board() {
+        let (mut state, mut ctl, mut config, feed) = game(vec![domino(); 3]);
+        config.detonation = Detonation::Manual;
+
+        // Permanent tiles everywhere but a hole for the block, and a
+        // ready square where the next one appears.
+        for y in 1..PG_HEIGHT - 1 {
+            for x in 1..PG_WIDTH - 1 {
+                if x <= 4 && y <= 4 {
+                    if x <= 3 && y <= 3 {
+                        state.pg.tiles.push((x, y, TileType::Plain(0)));
+                    }
+                } else if y != 6 || !(6..=7).contains(&x) {
+                    state.pg.tiles.push((x, y, TileType::Permanent));
+                }
+            }
+        }
+        state.blk = domino().moved_to(6, 6);
+        let input = Input {drop: true, .. Input::default()};
+        assert!(state.step_simulation(input, false, &mut ctl, &config, feed.now()).is_none());
+
+        assert!(state.pg.tiles.iter().all(|&(x, y, _)| x > 4 || y > 4));
+        assert_eq!(state.pg.at(6, 6), Some(TileType::Plain(0)));
+        assert!(state.score > 0);
+    }
+
+    #[test]
+    fn breakdown_adds_up() {
+        let mut pg = Block::from_map("*2*\n\
+                                      *C*\n\
+                                      1**\n").unwrap();
+        let xp = pg.explode();
+        let breakdown = bonus_breakdown(&xp.exploded);
+        assert_eq!(breakdown.len(), 3);
+        assert_eq!(breakdown.iter().map(|&(_, _, points)| points).sum::<u32>(),
+                   Scoring::new(xp.hits, 1, 1).total());
+    }
+
+    #[test]
+    fn scripted_game() {
+        let blocks = ["*+*\n", "***\n", "***\n", "1.2\n"].iter()
+            .map(|map| Block::from_map(map).unwrap())
+            .collect();
+        let (mut state, mut ctl, mut config, mut feed) = game(blocks);
+
+        // Stack the first three into a square, then drop the last one
+        // next to where that was.
+        let (r, d) = (nc::KEY_RIGHT, nc::KEY_DOWN);
+        let moves = [vec![r, r, r, r, d, d, d, d, d, d, d, d],
+                     vec![r, r, r, r, d, d, d, d, d, d, d],
+                     vec![r, r, r, r, d, d, d, d, d, d],
+                     vec![r, r, r, r, r, r, r, r, d, d, d, d, d, d, d, d]];
+        let grace = time::Duration::milliseconds(config.drop_grace as i64 + 1);
+        for keys in &moves {
+            // A key a frame, the drop past the drop grace.
+            for &key in keys.iter().chain(&['\r' as i32]) {
+                feed.now = feed.now + if key == '\r' as i32 { grace }
+                                      else { time::Duration::milliseconds(10) };
+                feed.keys = vec![key];
+                let input = state.handle_input(&mut ctl, &mut feed, &mut config, false)
+                    .ok().unwrap();
+                assert_eq!(input.drop, key == '\r' as i32);
+                assert!(state.step_simulation(input, false, &mut ctl, &config, feed.now())
+                        .is_none());
+            }
+        }
+
+        let mut grid = Grid::new(4 * state.pgw, 2 * state.pgh);
+        state.pg.paint(&mut grid, ctl.look);
+        state.bd.paint(&mut grid, ctl.look);
+        let expect = "┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓\n\
+                      ┃ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ ┃\n\
+                      ┃───┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                                       ┃───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                                       ┃───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                                       ┃───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                                       ┃───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                                       ┃───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                                       ┃───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                                       ┃───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                                       ┃───┃\n\
+                      ┃ ✖ ┃                                                       ┃ ✖ ┃\n\
+                      ┃───┃                                   ┏━━━┓   ┏━━━┓       ┃───┃\n\
+                      ┃ ✖ ┃                                   ┃ • ┃   ┃ •²┃       ┃ ✖ ┃\n\
+                      ┃───┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┻━━━┻━━━┻━━━┻━━━━━━━┛───┃\n\
+                      ┃ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ │ ✖ ┃\n\
+                      ┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┛\n";
+        assert_eq!(grid.to_string(), expect);
+        assert_eq!(state.score, 9);
+        assert_eq!(state.multiplier, 2);
+    }
+
+    #[test]
+    fn hourglass_keeps_the_gauges() {
+        let (mut state, mut ctl, config, mut feed) = game(vec![domino(), domino()]);
+        state.pg = ready_board();
+        state.pg.tiles.retain(|&(_, _, tt)| tt != TileType::Clock);
+        state.pg.tiles.push((6, 6, TileType::Hourglass));
+        state.blk = domino().moved_to(6, 7);
+        let outcome = simulate_drop(&state.pg, &state.blk, &state.bd).unwrap();
+        assert!(outcome.slowdown);
+
+        // Halfway through the multiplier gauge when the hourglass goes
+        // off.
+        state.multiplier = 2;
+        let now = feed.now();
+        state.last_mult_time = now - time::Duration::milliseconds(MULT_INTERVAL_MS / 2);
+        let input = Input {drop: true, .. Input::default()};
+        assert!(state.step_simulation(input, false, &mut ctl, &config, now).is_none());
+        assert!(state.slow_until.is_some());
+        let level = state.curve.level(state.score);
+        assert_eq!(state.drop_interval(),
+                   SLOW_FACTOR * state.difficulty.drop_interval(level, state.drop_time));
+        assert_eq!(state.mult_left(now), MULT_INTERVAL_MS / 2);
+
+        // And the same once it's over.
+        feed.now = now + time::Duration::milliseconds(SLOW_MS);
+        let (drop_left, mult_left) = (state.drop_left(feed.now), state.mult_left(feed.now));
+        state.end_slowdown(feed.now);
+        assert!(state.slow_until.is_none());
+        assert_eq!(state.drop_left(feed.now), drop_left);
+        assert_eq!(state.mult_left(feed.now), mult_left);
+    }
+
+    #[test]
+    fn meteors_rain_down() {
+        let (mut state, mut ctl, config, feed) = game(vec![domino(), domino()]);
+        state.pg = ready_board();
+        state.pg.tiles.retain(|&(_, _, tt)| tt != TileType::Clock);
+        state.pg.tiles.push((6, 6, TileType::Meteor));
+        state.blk = domino().moved_to(6, 7);
+        let outcome = simulate_drop(&state.pg, &state.blk, &state.bd).unwrap();
+        assert_eq!(outcome.meteors, 1);
+
+        let now = feed.now();
+        let input = Input {drop: true, .. Input::default()};
+        assert!(state.step_simulation(input, false, &mut ctl, &config, now).is_none());
+        assert_eq!(state.meteors.len(), METEOR_TILES);
+
+        // They land one after another, within the border and off the
+        // tiles that are there.
+        let tiles = state.pg.tiles.len();
+        let later = now + time::Duration::milliseconds(METEOR_MS * METEOR_TILES as i64);
+        assert!(state.step_simulation(Input::default(), false, &mut ctl, &config, later)
+                .is_none());
+        assert!(state.meteors.is_empty());
+        assert_eq!(state.pg.tiles.len(), tiles + METEOR_TILES);
+        assert!(!block_collides(&state.pg, &state.bd, &Block::new()));
+    }
+}